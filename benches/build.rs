@@ -0,0 +1,48 @@
+use core::fmt::Write as _;
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use heapless::String;
+use nutp::{FrameBuffer, MessageBuilder};
+
+/// The builder holds at most 16 headers, so that is the largest case.
+const HEADER_COUNTS: [usize; 3] = [1, 10, 16];
+
+fn builder(headers: usize) -> MessageBuilder {
+    let mut builder = MessageBuilder::new();
+    for i in 0..headers {
+        let mut key: String<32> = String::new();
+        let _ = write!(key, "X-Header-{}", i);
+        builder = builder
+            .add_header(key, String::try_from("some header value").unwrap())
+            .unwrap();
+    }
+    builder.set_body_str("hello world").unwrap()
+}
+
+fn bench_build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    for headers in HEADER_COUNTS {
+        group.bench_with_input(BenchmarkId::from_parameter(headers), &headers, |b, &n| {
+            b.iter_with_setup(|| builder(n), |builder| black_box(builder.build().unwrap()))
+        });
+    }
+    group.finish();
+}
+
+fn bench_to_bytes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("to_bytes");
+    for headers in HEADER_COUNTS {
+        let message = builder(headers).build().unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(headers), &message, |b, m| {
+            b.iter(|| {
+                let bytes: FrameBuffer = black_box(&m).to_bytes().unwrap();
+                black_box(bytes)
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_build, bench_to_bytes);
+criterion_main!(benches);
@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nutp::{DefaultMessage, Message};
+
+// Room for the largest frame a `DefaultMessage` can encode to: a fully escaped
+// header, body and trailer headers plus the framing around them
+const MAX_FRAME: usize = 16 * 1024;
+
+fuzz_target!(|data: &[u8]| {
+    // None of these may panic, whatever the input
+    let _ = Message::from_bytes_ref(data);
+    let _ = DefaultMessage::read_from(&mut &data[..]);
+
+    let Ok(message) = DefaultMessage::from_bytes(data) else {
+        return;
+    };
+
+    // Anything that parses must survive being sent again
+    let mut buf = [0u8; MAX_FRAME];
+    let len = message
+        .encode_into(&mut buf)
+        .expect("a parsed message fits in MAX_FRAME");
+    assert_eq!(DefaultMessage::from_bytes(&buf[..len]), Ok(message));
+});
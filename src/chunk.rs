@@ -0,0 +1,184 @@
+use core::fmt::Write as _;
+
+use heapless::{String, Vec};
+
+use crate::{BuildError, ChunkError, Message, MessageBuilder, MAX_BODY_SIZE};
+
+/// The header holding the zero-based position of a chunk.
+pub const CHUNK_INDEX_HEADER: &str = "Chunk-Index";
+
+/// The header holding how many chunks the payload was split into.
+pub const CHUNK_TOTAL_HEADER: &str = "Chunk-Total";
+
+impl<const H: usize, const B: usize> Message<H, B> {
+    /// Splits a payload that may be larger than `B` into a sequence of
+    /// messages, each carrying [`CHUNK_INDEX_HEADER`] and
+    /// [`CHUNK_TOTAL_HEADER`].
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The payload to split.
+    /// * `chunk_size` - The body size of each message, clamped to `1..=B`.
+    ///
+    /// # Return value
+    ///
+    /// An iterator over the chunk messages. An empty payload still produces
+    /// one empty chunk so the receiver sees it
+    pub fn split_body(
+        data: &[u8],
+        chunk_size: usize,
+    ) -> impl Iterator<Item = Result<Self, BuildError>> + '_ {
+        let chunk_size = chunk_size.clamp(1, B.max(1));
+        let total = data.len().div_ceil(chunk_size).max(1);
+
+        (0..total).map(move |index| {
+            let start = index * chunk_size;
+            let chunk = &data[start..data.len().min(start + chunk_size)];
+
+            let mut index_value = String::new();
+            let mut total_value = String::new();
+            // Can't fail, a usize has at most 20 digits
+            let _ = write!(index_value, "{}", index);
+            let _ = write!(total_value, "{}", total);
+
+            MessageBuilder::<H, B>::default()
+                .add_header(String::try_from(CHUNK_INDEX_HEADER).unwrap(), index_value)
+                .and_then(|b| {
+                    b.add_header(String::try_from(CHUNK_TOTAL_HEADER).unwrap(), total_value)
+                })
+                .ok_or(BuildError::TooManyHeaders)?
+                .set_body_bytes(Vec::from_slice(chunk).map_err(|_| BuildError::BodyTooLarge)?)
+                .build()
+        })
+    }
+}
+
+/// Collects the messages produced by [`Message::split_body`] back into the
+/// payload, holding up to `N` bytes of it.
+///
+/// Chunks must arrive in order. A chunk with index 0 always starts a new
+/// payload, dropping any partial one.
+#[derive(Debug)]
+pub struct ChunkReassembler<const N: usize = { MAX_BODY_SIZE * 4 }> {
+    payload: Vec<u8, N>,
+    next: usize,
+    total: usize,
+}
+
+impl<const N: usize> Default for ChunkReassembler<N> {
+    fn default() -> Self {
+        Self {
+            payload: Vec::new(),
+            next: 0,
+            total: 0,
+        }
+    }
+}
+
+impl ChunkReassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<const N: usize> ChunkReassembler<N> {
+    /// Appends the body of the next chunk.
+    pub fn push<const H: usize, const B: usize>(
+        &mut self,
+        message: &Message<H, B>,
+    ) -> Result<(), ChunkError> {
+        let number = |key| {
+            message
+                .header_value(key)
+                .and_then(|v| v.parse::<usize>().ok())
+                .ok_or(ChunkError::MissingHeader)
+        };
+        let index = number(CHUNK_INDEX_HEADER)?;
+        let total = number(CHUNK_TOTAL_HEADER)?;
+
+        if index == 0 {
+            self.payload.clear();
+            self.next = 0;
+            self.total = total;
+        }
+
+        if index != self.next || total != self.total || index >= total {
+            return Err(ChunkError::OutOfOrder {
+                expected: self.next,
+                found: index,
+            });
+        }
+
+        self.payload
+            .extend_from_slice(message.body())
+            .map_err(|_| ChunkError::TooLarge)?;
+        self.next += 1;
+
+        Ok(())
+    }
+
+    /// Takes the payload once its last chunk has been pushed.
+    ///
+    /// # Return value
+    ///
+    /// The reassembled payload, or `None` if chunks are still missing
+    pub fn take(&mut self) -> Option<Vec<u8, N>> {
+        if self.total == 0 || self.next != self.total {
+            return None;
+        }
+
+        self.next = 0;
+        self.total = 0;
+
+        Some(core::mem::take(&mut self.payload))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use heapless::Vec;
+
+    use crate::{ChunkError, ChunkReassembler, DefaultMessage, Message};
+
+    #[test]
+    fn test_round_trip() {
+        let data: [u8; 20] = core::array::from_fn(|i| i as u8);
+
+        let chunks: Vec<Message<64, 8>, 4> = Message::split_body(&data, 100)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[2].header_value("Chunk-Total"), Some("3"));
+
+        let mut reassembler = ChunkReassembler::<32>::default();
+        for chunk in &chunks {
+            assert_eq!(reassembler.take(), None);
+            reassembler.push(chunk).unwrap();
+        }
+
+        assert_eq!(reassembler.take().as_deref(), Some(&data[..]));
+        assert_eq!(reassembler.take(), None);
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let chunks: Vec<DefaultMessage, 3> = Message::split_body(b"abcdef", 2)
+            .map(Result::unwrap)
+            .collect();
+
+        let mut reassembler = ChunkReassembler::new();
+        reassembler.push(&chunks[0]).unwrap();
+
+        assert_eq!(
+            reassembler.push(&chunks[2]),
+            Err(ChunkError::OutOfOrder {
+                expected: 1,
+                found: 2
+            })
+        );
+        assert_eq!(
+            reassembler.push(&crate::MessageBuilder::new().build().unwrap()),
+            Err(ChunkError::MissingHeader)
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Heatshrink body compression, enabled by the `compress` feature.
+//!
+//! The framing is unchanged: a compressed body is sent like any other and
+//! marked with a `Content-Encoding: heatshrink` header. Both ends use a
+//! heatshrink window of 2^11 bytes and a lookahead of 2^4 bytes.
+
+use heapless::{String, Vec};
+
+use crate::{BuildError, Message, MessageBuilder, ParseError};
+
+/// The header naming how the body is encoded.
+pub const CONTENT_ENCODING_HEADER: &str = "Content-Encoding";
+
+/// The [`CONTENT_ENCODING_HEADER`] value of a heatshrink-compressed body.
+pub const HEATSHRINK_ENCODING: &str = "heatshrink";
+
+fn config() -> heatshrink::Config {
+    heatshrink::Config::new(11, 4).unwrap()
+}
+
+impl<const H: usize, const B: usize> MessageBuilder<H, B> {
+    /// Compresses `body` with heatshrink, sets it as the body and adds a
+    /// `Content-Encoding: heatshrink` header.
+    ///
+    /// `body` itself may be larger than `B`, as long as it compresses to at
+    /// most `B` bytes.
+    ///
+    /// # Return value
+    ///
+    /// The builder, [`BuildError::BodyTooLarge`] if the compressed body does
+    /// not fit in `B` bytes, or the error [`MessageBuilder::try_add_header`]
+    /// gives for the `Content-Encoding` header
+    pub fn set_body_compressed(self, body: &[u8]) -> Result<Self, BuildError> {
+        let mut compressed: Vec<u8, B> = Vec::new();
+        // Can't fail, the length is the capacity
+        let _ = compressed.resize(B, 0);
+
+        let len = heatshrink::encode(body, &mut compressed, &config())
+            .map_err(|_| BuildError::BodyTooLarge)?
+            .len();
+        compressed.truncate(len);
+
+        Ok(self
+            .try_add_header(
+                String::try_from(CONTENT_ENCODING_HEADER).unwrap(),
+                String::try_from(HEATSHRINK_ENCODING).unwrap(),
+            )?
+            .set_body_bytes(compressed))
+    }
+}
+
+impl<const H: usize, const B: usize> Message<H, B> {
+    /// The body with any `Content-Encoding` undone.
+    ///
+    /// A body without a `Content-Encoding` header is returned as it is. The
+    /// decompressed body must fit in the body capacity `B`, which keeps it
+    /// within [`MAX_BODY_SIZE`](crate::MAX_BODY_SIZE) for a
+    /// [`DefaultMessage`](crate::DefaultMessage).
+    ///
+    /// # Return value
+    ///
+    /// The decoded body, [`ParseError::UnsupportedEncoding`] for an encoding
+    /// other than heatshrink, or [`ParseError::BodyTooLarge`] if the
+    /// decompressed body does not fit in `B` bytes
+    pub fn decompressed_body(&self) -> Result<Vec<u8, B>, ParseError> {
+        match self.header_value(CONTENT_ENCODING_HEADER) {
+            // Can't fail, the body already fits in `B` bytes
+            None => Ok(Vec::from_slice(self.body()).unwrap()),
+            Some(encoding) if encoding.eq_ignore_ascii_case(HEATSHRINK_ENCODING) => {
+                let mut body: Vec<u8, B> = Vec::new();
+                // Can't fail, the length is the capacity
+                let _ = body.resize(B, 0);
+
+                let len = heatshrink::decode(self.body(), &mut body, &config())
+                    .map_err(|_| ParseError::BodyTooLarge)?
+                    .len();
+                body.truncate(len);
+
+                Ok(body)
+            }
+            Some(_) => Err(ParseError::UnsupportedEncoding),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{BuildError, DefaultMessage, FrameBuffer, Message, MessageBuilder, ParseError};
+
+    #[test]
+    fn test_round_trip() {
+        let payload =
+            br#"[{"t":21.5,"h":40},{"t":21.5,"h":40},{"t":21.6,"h":40},{"t":21.5,"h":41}]"#;
+
+        let message = MessageBuilder::new()
+            .set_body_compressed(payload)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(message.header_value("content-encoding"), Some("heatshrink"));
+        assert!(message.body().len() < payload.len());
+
+        let bytes: FrameBuffer = message.to_bytes().unwrap();
+        let parsed = DefaultMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.decompressed_body().unwrap(), &payload[..]);
+
+        let plain = MessageBuilder::new()
+            .set_body_str("plain")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(plain.decompressed_body().unwrap(), b"plain");
+    }
+
+    #[test]
+    fn test_limits() {
+        let payload = [7u8; 64];
+
+        let message: Message<64, 16> = MessageBuilder::default()
+            .set_body_compressed(&payload)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(
+            Message::<64, 16>::from_bytes(&message.to_bytes::<64>().unwrap())
+                .unwrap()
+                .decompressed_body(),
+            Err(ParseError::BodyTooLarge)
+        );
+
+        assert_eq!(
+            MessageBuilder::<64, 4>::default()
+                .set_body_compressed(b"not compressible")
+                .err(),
+            Some(BuildError::BodyTooLarge)
+        );
+
+        let gzip = MessageBuilder::new()
+            .add_header_str("Content-Encoding", "gzip")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(
+            gzip.decompressed_body(),
+            Err(ParseError::UnsupportedEncoding)
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use crate::consts::{EOT, ETX, NUL, SOH, STX};
+use crate::frame::{
+    crc16_update, encode_header_len, escaped_len, needs_escape, u16_to_u8s, FrameType, CRC16_INIT,
+    ESCAPE, ESCAPE_MASK, HEADER_LEN_OFFSET,
+};
+
+/// Builds a frame at compile time, for responses that never change.
+///
+/// The header is a string of `key: value` lines as
+/// [`Message::header`](crate::Message::header) returns it, and is not checked.
+/// Leave out `body` for a frame without a body section, and `frame_type` for a
+/// [`FrameType::Request`].
+///
+/// The frame is a `[u8; N]` of exactly the right length:
+///
+/// ```
+/// static NOT_FOUND: &[u8] = &nutp::frame!(
+///     header = "Status: 404",
+///     body = "Not Found",
+///     frame_type = nutp::FrameType::Response,
+/// );
+///
+/// let message = nutp::DefaultMessage::from_bytes(NOT_FOUND).unwrap();
+/// assert_eq!(message.status(), Some(404));
+/// ```
+#[macro_export]
+macro_rules! frame {
+    (@build $header:expr, $body:expr) => {
+        $crate::frame!(@build $header, $body, $crate::FrameType::Request)
+    };
+    (@build $header:expr, $body:expr, $frame_type:expr) => {{
+        const HEADER: &[u8] = str::as_bytes($header);
+        const BODY: Option<&[u8]> = $body;
+        const LEN: usize = $crate::const_frame_len(HEADER, BODY);
+        const FRAME: [u8; LEN] = $crate::const_frame($frame_type, HEADER, BODY);
+        FRAME
+    }};
+    (header = $header:expr, body = $body:expr $(, frame_type = $frame_type:expr)? $(,)?) => {
+        $crate::frame!(@build $header, Some(str::as_bytes($body)) $(, $frame_type)?)
+    };
+    (header = $header:expr $(, frame_type = $frame_type:expr)? $(,)?) => {
+        $crate::frame!(@build $header, None $(, $frame_type)?)
+    };
+}
+
+/// The length of the frame [`const_frame`] builds, as
+/// [`Message::encoded_len`](crate::Message::encoded_len).
+pub const fn const_frame_len(header: &[u8], body: Option<&[u8]>) -> usize {
+    let body_len = match body {
+        Some(body) => 2 + escaped_len(body) + 1,
+        None => 0,
+    };
+
+    let (_, _, len_size) = encode_header_len(escaped_len(header));
+
+    HEADER_LEN_OFFSET
+        + len_size
+        + escaped_len(header)
+        + 1
+        + body_len
+        + escaped_len(&u16_to_u8s(checksum(header, body)))
+        + 1
+}
+
+/// Builds the frame [`Message::to_bytes`](crate::Message::to_bytes) would
+/// for a message with this header block and body, in `const` context.
+///
+/// # Panics
+///
+/// If `N` is not [`const_frame_len`] of the same header and body, or the
+/// escaped header is longer than a four byte length can hold. In `const`
+/// context this is a compile error.
+pub const fn const_frame<const N: usize>(
+    frame_type: FrameType,
+    header: &[u8],
+    body: Option<&[u8]>,
+) -> [u8; N] {
+    assert!(
+        escaped_len(header) as u64 <= u32::MAX as u64,
+        "header is too long to frame"
+    );
+    let (version, header_len, len_size) = encode_header_len(escaped_len(header));
+
+    let (header_len, _) = header_len.split_at(len_size);
+    let mut w = Writer {
+        buf: [0; N],
+        pos: 0,
+    }
+    .push(SOH)
+    .push(STX)
+    .push(version)
+    .push(frame_type as u8)
+    .push_raw(header_len)
+    .push_escaped(header)
+    .push(NUL);
+
+    if let Some(body) = body {
+        w = w.push(ETX).push(STX).push_escaped(body).push(NUL);
+    }
+
+    let w = w
+        .push_escaped(&u16_to_u8s(checksum(header, body)))
+        .push(EOT);
+    assert!(w.pos == N, "N is not the length of the frame");
+
+    w.buf
+}
+
+/// The checksum of a header and optional body.
+const fn checksum(header: &[u8], body: Option<&[u8]>) -> u16 {
+    let crc = crc16_update(CRC16_INIT, header);
+    match body {
+        Some(body) => crc16_update(crc, body),
+        None => crc,
+    }
+}
+
+/// Fills a frame buffer front to back, by value so it works in `const fn`.
+struct Writer<const N: usize> {
+    buf: [u8; N],
+    pos: usize,
+}
+
+impl<const N: usize> Writer<N> {
+    const fn push(mut self, byte: u8) -> Self {
+        self.buf[self.pos] = byte;
+        self.pos += 1;
+        self
+    }
+
+    const fn push_raw(mut self, input: &[u8]) -> Self {
+        let mut i = 0;
+        while i < input.len() {
+            self = self.push(input[i]);
+            i += 1;
+        }
+        self
+    }
+
+    const fn push_escaped(mut self, input: &[u8]) -> Self {
+        let mut i = 0;
+        while i < input.len() {
+            self = if needs_escape(input[i]) {
+                self.push(ESCAPE).push(input[i] ^ ESCAPE_MASK)
+            } else {
+                self.push(input[i])
+            };
+            i += 1;
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{DefaultMessage, FrameType, MessageBuilder};
+
+    #[test]
+    fn test_matches_to_bytes() {
+        static FRAME: &[u8] = &frame!(header = "Id: 1\r\nType: \u{1}", body = "a\u{0}b");
+
+        let message = MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .add_header_str("Type", "\u{1}")
+            .unwrap()
+            .set_body_str("a\u{0}b")
+            .unwrap()
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+
+        assert_eq!(FRAME, &bytes[..]);
+        assert_eq!(DefaultMessage::from_bytes(FRAME), Ok(message));
+    }
+
+    #[test]
+    fn test_no_body() {
+        const PING: [u8; 10] = frame!(header = "", frame_type = FrameType::Ping);
+
+        let bytes: crate::FrameBuffer = DefaultMessage::ping().to_bytes().unwrap();
+        assert_eq!(&PING[..], &bytes[..]);
+        assert!(DefaultMessage::from_bytes(&PING).unwrap().is_ping());
+    }
+}
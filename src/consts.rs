@@ -0,0 +1,28 @@
+//! The control bytes that delimit the parts of a frame.
+//!
+//! A frame is laid out as
+//! `SOH STX version type [seq] header_len header NUL [ETX STX body NUL]
+//! [ETX SOH trailers NUL] checksum EOT`, with the sequence number, body and
+//! trailer sections left out when a message has none. None of these bytes
+//! appear raw inside the header, body, trailers or checksum, where they are
+//! escaped with [`ESCAPE`](crate::ESCAPE).
+
+/// Terminates the header and the body.
+///
+/// Both sections are treated the same way: a `0x0` in the content of either
+/// is escaped, so it is kept on a round trip and never ends the section
+/// early.
+pub const NUL: u8 = 0x0;
+
+/// Starts a frame.
+pub const SOH: u8 = 0x1;
+
+/// Follows [`SOH`] at the start of a frame and [`ETX`] at the start of the
+/// body.
+pub const STX: u8 = 0x2;
+
+/// Starts the body section.
+pub const ETX: u8 = 0x3;
+
+/// Ends a frame.
+pub const EOT: u8 = 0x4;
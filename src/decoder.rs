@@ -0,0 +1,232 @@
+use heapless::Vec;
+
+use crate::consts::SOH;
+use crate::frame::{frame_len, min_frame_len};
+use crate::{FrameTooLarge, Message, FRAME_OVERHEAD, MAX_BODY_SIZE};
+
+/// Reassembles frames that arrive split across several reads.
+///
+/// Bytes are buffered with [`FrameDecoder::push`] and complete frames are
+/// taken out with [`FrameDecoder::poll`]. Up to `N` bytes are buffered, so `N`
+/// must fit the largest frame expected on the link.
+///
+/// [`FrameDecoder::with_max_size`] caps a single frame below `N`, so a peer
+/// that never sends an end marker is noticed instead of filling the buffer.
+#[derive(Debug)]
+pub struct FrameDecoder<
+    const N: usize = { MAX_BODY_SIZE * 2 + FRAME_OVERHEAD },
+    const H: usize = MAX_BODY_SIZE,
+    const B: usize = MAX_BODY_SIZE,
+> {
+    buffer: Vec<u8, N>,
+    max: usize,
+}
+
+impl<const N: usize, const H: usize, const B: usize> Default for FrameDecoder<N, H, B> {
+    fn default() -> Self {
+        Self {
+            buffer: Vec::new(),
+            max: N,
+        }
+    }
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<const N: usize, const H: usize, const B: usize> FrameDecoder<N, H, B> {
+    /// Makes a decoder that gives up on a frame once more than `max` of its
+    /// bytes are buffered, as reported by [`FrameDecoder::push`].
+    ///
+    /// # Arguments
+    ///
+    /// * `max` - The largest frame accepted, capped at `N`.
+    pub fn with_max_size(max: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            max: max.min(N),
+        }
+    }
+
+    /// Buffers as many of `bytes` as fit.
+    ///
+    /// # Return value
+    ///
+    /// The number of bytes taken from `bytes`. Anything left over should be
+    /// pushed again after the next [`FrameDecoder::poll`].
+    ///
+    /// [`FrameTooLarge`] if the frame being buffered grew past the maximum
+    /// size without ending, or its header length already rules out fitting
+    /// in it. The buffer is then cleared, dropping the rest of `bytes` along
+    /// with it
+    pub fn push(&mut self, bytes: &[u8]) -> Result<usize, FrameTooLarge> {
+        let taken = bytes.len().min(N - self.buffer.len());
+        // Can't fail, `taken` is at most the remaining capacity
+        let _ = self.buffer.extend_from_slice(&bytes[..taken]);
+
+        self.resync();
+        let len = frame_len(&self.buffer).unwrap_or_else(|| {
+            let min = min_frame_len(&self.buffer).unwrap_or_default();
+            min.max(self.buffer.len())
+        });
+        if len > self.max {
+            self.buffer.clear();
+            return Err(FrameTooLarge);
+        }
+
+        Ok(taken)
+    }
+
+    /// Takes the next complete frame out of the buffer.
+    ///
+    /// Bytes before a `0x1 0x2` start sequence are dropped. A frame that fails
+    /// to parse may be noise or a truncated frame that ran into the next one,
+    /// so only its start sequence is dropped and the search goes on from
+    /// there, as in [`Message::from_bytes_scanning`]. Bytes after the frame
+    /// stay buffered for the next call.
+    ///
+    /// # Return value
+    ///
+    /// The decoded message, or `None` if no complete frame is buffered yet
+    pub fn poll(&mut self) -> Option<Message<H, B>> {
+        loop {
+            self.resync();
+
+            let Some(len) = frame_len(&self.buffer) else {
+                if self.buffer.is_full() {
+                    // The frame can never complete, look for one after it
+                    self.consume(1);
+                    continue;
+                }
+                return None;
+            };
+
+            match Message::from_bytes(&self.buffer[..len]) {
+                Ok(message) => {
+                    self.consume(len);
+                    return Some(message);
+                }
+                Err(_) => self.consume(1),
+            }
+        }
+    }
+
+    /// Drops everything before the first `0x1 0x2` start sequence.
+    fn resync(&mut self) {
+        let start = Message::find_frame_start(&self.buffer).unwrap_or(match self.buffer.last() {
+            // The start sequence may be split across reads
+            Some(&SOH) => self.buffer.len() - 1,
+            _ => self.buffer.len(),
+        });
+
+        self.consume(start);
+    }
+
+    /// Removes the first `len` bytes of the buffer.
+    fn consume(&mut self, len: usize) {
+        self.buffer.copy_within(len.., 0);
+        self.buffer.truncate(self.buffer.len() - len);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::str::FromStr;
+
+    use heapless::String;
+
+    use crate::{FrameBuffer, FrameDecoder, FrameTooLarge, MessageBuilder};
+
+    #[test]
+    fn test_split_frames() {
+        let message = MessageBuilder::new()
+            .add_header(
+                String::from_str("Content-Type").unwrap(),
+                String::from_str("text/plain").unwrap(),
+            )
+            .unwrap()
+            .set_body(String::from_str("hello").unwrap())
+            .build()
+            .unwrap();
+        let bytes: FrameBuffer = message.to_bytes().unwrap();
+
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&[0xff, 0x4, 0x2]).unwrap();
+        decoder.push(&bytes[..7]).unwrap();
+        assert_eq!(decoder.poll(), None);
+
+        decoder.push(&bytes[7..]).unwrap();
+        decoder.push(&bytes[..3]).unwrap();
+        assert_eq!(decoder.poll(), Some(message.clone()));
+        assert_eq!(decoder.poll(), None);
+
+        decoder.push(&bytes[3..]).unwrap();
+        assert_eq!(decoder.poll(), Some(message));
+    }
+
+    #[test]
+    fn test_resync_after_bad_frame() {
+        let message = MessageBuilder::new()
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+        let bytes: FrameBuffer = message.to_bytes().unwrap();
+
+        // A false start whose header length runs into the real frame
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&[0x1, 0x2, 0x2, 0x0, 0x1, 0x0]).unwrap();
+        decoder.push(&bytes).unwrap();
+        assert_eq!(decoder.poll(), Some(message.clone()));
+        assert_eq!(decoder.poll(), None);
+
+        // A frame cut short before its end marker
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&bytes[..bytes.len() - 4]).unwrap();
+        decoder.push(&bytes).unwrap();
+        assert_eq!(decoder.poll(), Some(message));
+        assert_eq!(decoder.poll(), None);
+    }
+
+    #[test]
+    fn test_oversized_frame() {
+        let mut decoder: FrameDecoder<16> = FrameDecoder::default();
+
+        assert_eq!(decoder.push(&[0x1, 0x2, 0x2, 0x0, 0x6, 0x0]), Ok(6));
+        assert_eq!(decoder.push(&[b'a'; 32]), Ok(10));
+        assert_eq!(decoder.poll(), None);
+        assert_eq!(decoder.push(&[b'a'; 32]), Ok(16));
+    }
+
+    #[test]
+    fn test_max_size() {
+        let message = MessageBuilder::new()
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+        let bytes: FrameBuffer = message.to_bytes().unwrap();
+
+        let mut decoder: FrameDecoder = FrameDecoder::with_max_size(bytes.len());
+        assert_eq!(decoder.push(&bytes), Ok(bytes.len()));
+        assert_eq!(decoder.poll(), Some(message.clone()));
+
+        assert_eq!(decoder.push(&[0x1, 0x2, 0x2, 0x0, 0x0, 0x0]), Ok(6));
+        assert_eq!(decoder.push(&[b'a'; 32]), Err(FrameTooLarge));
+        assert_eq!(decoder.poll(), None);
+
+        assert_eq!(decoder.push(&bytes), Ok(bytes.len()));
+        assert_eq!(decoder.poll(), Some(message.clone()));
+
+        // The header length alone rules the frame out
+        assert_eq!(
+            decoder.push(&[0x1, 0x2, 0x2, 0x0, 0xff, 0x0]),
+            Err(FrameTooLarge)
+        );
+        assert_eq!(decoder.push(&bytes), Ok(bytes.len()));
+        assert_eq!(decoder.poll(), Some(message));
+    }
+}
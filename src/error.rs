@@ -0,0 +1,247 @@
+use core::fmt::{Debug, Display};
+
+/// The reasons [`Message::from_bytes`](crate::Message::from_bytes) can reject
+/// a frame.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum ParseError {
+    /// The input is too short to hold even an empty frame.
+    TooShort,
+    /// The input does not start with the `0x1 0x2` magic.
+    BadStartMarker,
+    /// The frame was written with a protocol version this crate can't read.
+    UnsupportedVersion { found: u8 },
+    /// The frame type byte is not a known [`FrameType`](crate::FrameType).
+    UnknownFrameType { found: u8 },
+    /// The last byte of the input is not the `0x4` end marker.
+    BadEndMarker { found: u8 },
+    /// The header is not followed by its `0x0` terminator.
+    MissingHeaderTerminator,
+    /// No `0x2` body marker follows the header.
+    MissingBodyMarker,
+    /// No `0x1` trailer marker follows the body.
+    MissingTrailerMarker,
+    /// The trailer headers are longer than
+    /// [`MAX_TRAILER_LEN`](crate::MAX_TRAILER_LEN).
+    TrailerTooLarge,
+    /// The header length read from the wire does not fit in the input.
+    HeaderLengthOverflow,
+    /// The body does not fit in a message.
+    BodyTooLarge,
+    /// The header is not valid UTF-8.
+    InvalidUtf8,
+    /// A line of the header block is not a well-formed `key: value` pair.
+    InvalidHeader,
+    /// An escape byte is not followed by an escaped marker.
+    BadEscape,
+    /// The checksum trailer before the end marker is missing or truncated.
+    MissingChecksum,
+    /// The checksum in the trailer does not match the header and body.
+    ChecksumMismatch { expected: u16, found: u16 },
+    /// The body has a `Content-Encoding` this crate can't decode.
+    UnsupportedEncoding,
+    /// The header block has more than [`MAX_HEADERS`](crate::MAX_HEADERS)
+    /// lines.
+    TooManyHeaders,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ParseError::TooShort => f.write_str("input is too short to be a frame"),
+            ParseError::BadStartMarker => f.write_str("frame does not start with 0x1 0x2"),
+            ParseError::UnsupportedVersion { found } => {
+                write!(f, "unsupported protocol version {}", found)
+            }
+            ParseError::UnknownFrameType { found } => write!(f, "unknown frame type {}", found),
+            ParseError::BadEndMarker { found } => {
+                write!(f, "frame ends with {:#x} instead of 0x4", found)
+            }
+            ParseError::MissingHeaderTerminator => {
+                f.write_str("header is not followed by a 0x0 terminator")
+            }
+            ParseError::MissingBodyMarker => f.write_str("no body marker after the header"),
+            ParseError::MissingTrailerMarker => f.write_str("no trailer marker after the body"),
+            ParseError::TrailerTooLarge => f.write_str("trailer headers are too long"),
+            ParseError::HeaderLengthOverflow => {
+                f.write_str("header length is larger than the frame")
+            }
+            ParseError::BodyTooLarge => f.write_str("body is larger than the body capacity"),
+            ParseError::InvalidUtf8 => f.write_str("header is not valid UTF-8"),
+            ParseError::InvalidHeader => f.write_str("header line is not a key: value pair"),
+            ParseError::BadEscape => f.write_str("escape byte is not followed by a marker"),
+            ParseError::MissingChecksum => f.write_str("frame has no checksum trailer"),
+            ParseError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "checksum {:#06x} does not match the expected {:#06x}",
+                found, expected
+            ),
+            ParseError::UnsupportedEncoding => f.write_str("body has an unsupported encoding"),
+            ParseError::TooManyHeaders => f.write_str("header block has too many lines"),
+        }
+    }
+}
+
+/// A fixed-capacity buffer had no room left for the data written to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapacityError;
+
+impl Display for CapacityError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("buffer capacity exceeded")
+    }
+}
+
+/// A [`FrameDecoder`](crate::FrameDecoder) buffered more bytes of a frame
+/// than its maximum size without seeing the end marker.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameTooLarge;
+
+impl Display for FrameTooLarge {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("frame is larger than the decoder's maximum size")
+    }
+}
+
+/// The reasons [`Message::read_from`](crate::Message::read_from) can fail.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ReadError<E> {
+    /// The source failed to produce the bytes of the frame.
+    Io(E),
+    /// The bytes that were read are not a valid frame.
+    Parse(ParseError),
+    /// The frame was not complete before the deadline given to
+    /// [`Message::read_from_timeout`](crate::Message::read_from_timeout).
+    Timeout,
+}
+
+impl<E> From<ParseError> for ReadError<E> {
+    fn from(value: ParseError) -> Self {
+        ReadError::Parse(value)
+    }
+}
+
+impl<E: Debug> Display for ReadError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "failed to read frame: {:?}", e),
+            ReadError::Parse(e) => write!(f, "failed to parse frame: {}", e),
+            ReadError::Timeout => f.write_str("timed out before the frame was complete"),
+        }
+    }
+}
+
+/// The reasons [`MessageBuilder::build`](crate::MessageBuilder::build) can
+/// fail.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// The header at `index`, in the order the headers were added, does not
+    /// fit in the header block.
+    HeaderTooLarge { index: usize },
+    /// The builder has no room for another header.
+    TooManyHeaders,
+    /// The builder already has a header with this name.
+    DuplicateHeader,
+    /// A header name or value would break the `key: value` line format.
+    InvalidHeader,
+    /// A header name has a `:`, a control byte or a non-ASCII byte.
+    InvalidHeaderKey,
+    /// A header name is empty, which would make a line starting with `:`.
+    EmptyHeaderKey,
+    /// A header value has a `\r` or `\n` that would start another line.
+    InvalidHeaderValue,
+    /// The body does not fit in the body capacity.
+    BodyTooLarge,
+    /// The trailer headers would be longer than
+    /// [`MAX_TRAILER_LEN`](crate::MAX_TRAILER_LEN).
+    TrailerTooLarge,
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            BuildError::HeaderTooLarge { index } => {
+                write!(f, "header {} does not fit in the header block", index)
+            }
+            BuildError::TooManyHeaders => f.write_str("too many headers"),
+            BuildError::DuplicateHeader => f.write_str("header is already present"),
+            BuildError::InvalidHeader => f.write_str("header name or value is malformed"),
+            BuildError::InvalidHeaderKey => f.write_str("header name has a forbidden byte"),
+            BuildError::EmptyHeaderKey => f.write_str("header name is empty"),
+            BuildError::InvalidHeaderValue => f.write_str("header value has a line break"),
+            BuildError::BodyTooLarge => f.write_str("body is larger than the body capacity"),
+            BuildError::TrailerTooLarge => f.write_str("trailer headers are too long"),
+        }
+    }
+}
+
+/// The part of the frame that
+/// [`Message::to_bytes`](crate::Message::to_bytes) ran out of room in.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum EncodeError {
+    /// The start markers, version or header length.
+    PreambleOverflow,
+    /// The header or its terminator.
+    HeaderOverflow,
+    /// The body markers, the body or its terminator.
+    BodyOverflow,
+    /// The trailer headers, the checksum or the end marker.
+    TrailerOverflow,
+}
+
+impl Display for EncodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let part = match self {
+            EncodeError::PreambleOverflow => "preamble",
+            EncodeError::HeaderOverflow => "header",
+            EncodeError::BodyOverflow => "body",
+            EncodeError::TrailerOverflow => "trailer",
+        };
+        write!(f, "output buffer is full while writing the {}", part)
+    }
+}
+
+/// The reasons [`ChunkReassembler::push`](crate::ChunkReassembler::push) can
+/// reject a chunk.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ChunkError {
+    /// The message has no numeric `Chunk-Index` or `Chunk-Total` header.
+    MissingHeader,
+    /// The chunk is not the one that was expected next.
+    OutOfOrder { expected: usize, found: usize },
+    /// The payload does not fit in the reassembly buffer.
+    TooLarge,
+}
+
+impl Display for ChunkError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ChunkError::MissingHeader => f.write_str("message has no chunk headers"),
+            ChunkError::OutOfOrder { expected, found } => {
+                write!(f, "expected chunk {} but got chunk {}", expected, found)
+            }
+            ChunkError::TooLarge => f.write_str("payload is larger than the reassembly buffer"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+mod std_impls {
+    use core::fmt::Debug;
+
+    use super::*;
+
+    impl std::error::Error for ParseError {}
+    impl std::error::Error for CapacityError {}
+    impl std::error::Error for FrameTooLarge {}
+    impl std::error::Error for BuildError {}
+    impl std::error::Error for EncodeError {}
+    impl std::error::Error for ChunkError {}
+
+    impl<E: Debug> std::error::Error for ReadError<E> {}
+}
@@ -0,0 +1,558 @@
+//! The wire format: the frame layout, escaping, the checksum and the header
+//! length, and finding the sections of a frame without decoding them.
+//!
+//! Nothing here knows what a header line is; [`Message`](crate::Message)
+//! builds on these pieces to turn the sections into headers and a body.
+
+use heapless::Vec;
+
+use crate::consts::{EOT, ETX, NUL, SOH, STX};
+use crate::{io, ParseError, ReadError};
+
+/// The bytes a frame adds around its header and body: the start markers,
+/// version, frame type, sequence number and header length, the header
+/// terminator, the body markers and terminator, a fully escaped checksum and
+/// the end marker.
+pub const FRAME_OVERHEAD: usize = HEADER_OFFSET + 2 + 1 + 2 + 1 + 2 * 2 + 1;
+
+/// The version of the wire format written by
+/// [`Message::to_bytes`](crate::Message::to_bytes), sent right after the start
+/// markers. Frames of any other version are rejected.
+///
+/// Version 2 added the [`FrameType`] byte and trailer headers.
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// The version of frames whose escaped header is too long for the two byte
+/// header length of [`PROTOCOL_VERSION`]. They are otherwise the same, but
+/// send the header length in four bytes.
+pub const LONG_HEADER_VERSION: u8 = 3;
+
+/// Where the frame type byte is, after the markers and version.
+pub(crate) const FRAME_TYPE_OFFSET: usize = 3;
+
+/// Where the two header length bytes start, after the frame type, in a frame
+/// without a sequence number. In a frame with one, the sequence number starts
+/// here instead.
+pub(crate) const HEADER_LEN_OFFSET: usize = FRAME_TYPE_OFFSET + 1;
+
+/// Set in the frame type byte of a frame that carries a sequence number, sent
+/// as two little-endian bytes between the frame type and the header length.
+pub(crate) const SEQ_FLAG: u8 = 0x80;
+
+/// Where the header length bytes start in a frame whose frame type byte is
+/// `type_byte`.
+pub(crate) const fn header_len_offset(type_byte: u8) -> usize {
+    if type_byte & SEQ_FLAG != 0 {
+        HEADER_LEN_OFFSET + 2
+    } else {
+        HEADER_LEN_OFFSET
+    }
+}
+
+/// Where the header itself starts in a [`PROTOCOL_VERSION`] frame.
+pub(crate) const HEADER_OFFSET: usize = HEADER_LEN_OFFSET + 2;
+
+/// The start markers, the version, the frame type, the header length bytes
+/// and the end marker.
+pub(crate) const MIN_FRAME_SIZE: usize = HEADER_OFFSET + 1;
+
+/// What a frame is for, sent right after the version so a receiver can
+/// dispatch on it without looking at the headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u8)]
+pub enum FrameType {
+    /// Asks the peer to do something. A
+    /// [`MessageBuilder`](crate::MessageBuilder) makes requests unless told
+    /// otherwise.
+    #[default]
+    Request = 0,
+    /// Answers a request.
+    Response = 1,
+    /// Reports something without expecting an answer.
+    Event = 2,
+    /// Keeps the link alive, as [`Message::ping`](crate::Message::ping).
+    Ping = 3,
+}
+
+impl FrameType {
+    /// The frame type sent as `byte`, or `None` if there is none.
+    pub const fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(FrameType::Request),
+            1 => Some(FrameType::Response),
+            2 => Some(FrameType::Event),
+            3 => Some(FrameType::Ping),
+            _ => None,
+        }
+    }
+}
+
+/// Precedes an escaped byte inside the header or body.
+///
+/// The markers `0x0` to `0x4` and the escape byte itself never appear raw
+/// inside either section. Instead they are sent as `ESCAPE` followed by the
+/// byte XORed with [`ESCAPE_MASK`].
+pub const ESCAPE: u8 = 0x1B;
+
+/// XORed onto an escaped byte so the byte after [`ESCAPE`] is never a marker.
+pub const ESCAPE_MASK: u8 = 0x20;
+
+pub(crate) const fn needs_escape(byte: u8) -> bool {
+    byte <= EOT || byte == ESCAPE
+}
+
+/// The most bytes that `capacity` bytes of content can take up once escaped.
+pub(crate) const fn max_escaped_len(capacity: usize) -> usize {
+    capacity * 2
+}
+
+/// The number of bytes `input` takes up on the wire once escaped.
+pub(crate) const fn escaped_len(input: &[u8]) -> usize {
+    let mut len = input.len();
+    let mut i = 0;
+    while i < input.len() {
+        if needs_escape(input[i]) {
+            len += 1;
+        }
+        i += 1;
+    }
+    len
+}
+
+/// Writes `input` to `w`, escaping any marker bytes.
+///
+/// Runs of bytes that need no escaping are written with a single call.
+pub(crate) fn write_escaped<W: io::Write>(w: &mut W, mut input: &[u8]) -> Result<(), W::Error> {
+    while let Some(pos) = input.iter().position(|&b| needs_escape(b)) {
+        w.write_all(&input[..pos])?;
+        w.write_all(&[ESCAPE, input[pos] ^ ESCAPE_MASK])?;
+        input = &input[pos + 1..];
+    }
+
+    w.write_all(input)
+}
+
+/// Undoes [`write_escaped`], yielding the original bytes of an escaped region.
+pub(crate) struct Unescape<'a> {
+    input: core::slice::Iter<'a, u8>,
+}
+
+impl<'a> Unescape<'a> {
+    pub(crate) fn new(input: &'a [u8]) -> Self {
+        Self {
+            input: input.iter(),
+        }
+    }
+}
+
+impl Iterator for Unescape<'_> {
+    type Item = Result<u8, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let byte = *self.input.next()?;
+
+        if byte != ESCAPE {
+            return Some(Ok(byte));
+        }
+
+        match self.input.next() {
+            Some(&escaped) if needs_escape(escaped ^ ESCAPE_MASK) => {
+                Some(Ok(escaped ^ ESCAPE_MASK))
+            }
+            _ => Some(Err(ParseError::BadEscape)),
+        }
+    }
+}
+
+/// The CRC16 (CCITT) polynomial used for the frame checksum.
+pub const CRC16_POLY: u16 = 0x1021;
+
+/// The initial CRC16 register value, giving the CCITT-FALSE variant.
+pub const CRC16_INIT: u16 = 0xFFFF;
+
+/// Feeds `input` into the running CRC16 checksum `crc`.
+pub(crate) const fn crc16_update(mut crc: u16, input: &[u8]) -> u16 {
+    let mut i = 0;
+    while i < input.len() {
+        crc ^= (input[i] as u16) << 8;
+
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ CRC16_POLY
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+
+        i += 1;
+    }
+
+    crc
+}
+
+/// Reads a single byte from `r`.
+pub(crate) fn read_byte<R: io::Read>(r: &mut R) -> Result<u8, ReadError<R::Error>> {
+    let mut byte = [0u8];
+    r.read_exact(&mut byte).map_err(ReadError::Io)?;
+    Ok(byte[0])
+}
+
+/// Reads the byte that follows an [`ESCAPE`] and restores the marker.
+pub(crate) fn read_escaped<R: io::Read>(r: &mut R) -> Result<u8, ReadError<R::Error>> {
+    let byte = read_byte(r)? ^ ESCAPE_MASK;
+    if !needs_escape(byte) {
+        return Err(ParseError::BadEscape.into());
+    }
+    Ok(byte)
+}
+
+/// Reads escaped content up to and including its `0x0` terminator.
+///
+/// # Arguments
+///
+/// * `r` - The source to read from.
+/// * `out` - Where the unescaped content goes.
+/// * `full` - The error to return if `out` runs out of room.
+pub(crate) fn read_section<R: io::Read, const N: usize>(
+    r: &mut R,
+    out: &mut Vec<u8, N>,
+    full: ParseError,
+) -> Result<(), ReadError<R::Error>> {
+    loop {
+        let byte = match read_byte(r)? {
+            NUL => return Ok(()),
+            EOT => return Err(ParseError::MissingChecksum.into()),
+            ESCAPE => read_escaped(r)?,
+            byte => byte,
+        };
+        out.push(byte).map_err(|_| full.clone())?;
+    }
+}
+
+/// The byte order of the header length field, for
+/// [`Message::from_bytes_endian`](crate::Message::from_bytes_endian).
+///
+/// Little-endian is canonical: it is what this crate writes and what
+/// [`Message::from_bytes`](crate::Message::from_bytes) reads. Big-endian is
+/// only read, for peers that send the length the other way round. The
+/// checksum is little-endian either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness {
+    /// Least significant byte first, the canonical order.
+    #[default]
+    Le,
+    /// Most significant byte first.
+    Be,
+}
+
+/// The number of header length bytes in a frame of `version`, or `None` for a
+/// version this crate can't read.
+pub(crate) const fn header_len_size(version: u8) -> Option<usize> {
+    match version {
+        PROTOCOL_VERSION => Some(2),
+        LONG_HEADER_VERSION => Some(4),
+        _ => None,
+    }
+}
+
+/// The version and header length bytes for an escaped header of `len` bytes:
+/// [`PROTOCOL_VERSION`] if the length fits in two bytes and
+/// [`LONG_HEADER_VERSION`] otherwise.
+///
+/// # Return value
+///
+/// The version, the little-endian length and how many of its bytes are sent
+///
+/// `len` must fit in four bytes, and there is no error for when it doesn't
+/// because no caller can pass a longer one: a [`Message`](crate::Message)
+/// checks at compile time that its header capacity fits, and
+/// [`const_frame`](crate::const_frame) checks its header before calling this.
+pub(crate) const fn encode_header_len(len: usize) -> (u8, [u8; 4], usize) {
+    let bytes = (len as u32).to_le_bytes();
+    if len <= u16::MAX as usize {
+        (PROTOCOL_VERSION, bytes, 2)
+    } else {
+        (LONG_HEADER_VERSION, bytes, 4)
+    }
+}
+
+/// Reads a header length of `bytes.len()` bytes in the given byte order.
+///
+/// # Return value
+///
+/// The length, or `None` if it doesn't fit in a `usize`
+pub(crate) fn decode_header_len(bytes: &[u8], endian: Endianness) -> Option<usize> {
+    let push = |len: u32, &b: &u8| len << 8 | u32::from(b);
+    let len = match endian {
+        Endianness::Le => bytes.iter().rev().fold(0, push),
+        Endianness::Be => bytes.iter().fold(0, push),
+    };
+    usize::try_from(len).ok()
+}
+
+/// Finds where the frame at the start of `input` ends.
+///
+/// The header length field is read to skip the header, since it is the only
+/// field after the start markers that may hold a raw `0x4`.
+///
+/// # Return value
+///
+/// The length of the frame including its end marker, or `None` if `input`
+/// does not yet hold the whole frame
+pub(crate) fn frame_len(input: &[u8]) -> Option<usize> {
+    let header_end = header_end(input)?;
+    let end = input.get(header_end..)?.iter().position(|&a| a == EOT)?;
+
+    Some(header_end + end + 1)
+}
+
+/// The shortest the frame at the start of `input` can be, going by its header
+/// length: the header followed by its terminator, an unescaped checksum and
+/// the end marker.
+///
+/// # Return value
+///
+/// The lower bound, or `None` if `input` does not yet hold the header length
+pub(crate) fn min_frame_len(input: &[u8]) -> Option<usize> {
+    let (start, len) = header_span(input)?;
+    min_frame_len_for(start, len)
+}
+
+/// The shortest a frame can be whose header of `header_len` bytes starts at
+/// `header_start`, as in [`min_frame_len`].
+///
+/// # Return value
+///
+/// The lower bound, or `None` if it overflows a `usize`
+pub(crate) fn min_frame_len_for(header_start: usize, header_len: usize) -> Option<usize> {
+    header_start.checked_add(header_len)?.checked_add(1 + 2 + 1)
+}
+
+/// Where the header of the frame at the start of `input` ends, going by its
+/// header length.
+///
+/// # Return value
+///
+/// The offset of the header terminator, or `None` if `input` does not yet
+/// hold the header length
+pub(crate) fn header_end(input: &[u8]) -> Option<usize> {
+    let (start, len) = header_span(input)?;
+    start.checked_add(len)
+}
+
+/// Where the header starts in a frame with the given version and frame type
+/// bytes, after the sequence number if there is one and the header length.
+pub(crate) fn header_start(version: u8, type_byte: u8) -> usize {
+    // An unknown version is read as the shortest length, since the frame is
+    // rejected once it is parsed anyway
+    header_len_offset(type_byte) + header_len_size(version).unwrap_or(2)
+}
+
+/// Where the header of the frame at the start of `input` starts and how long
+/// its header length field says it is.
+fn header_span(input: &[u8]) -> Option<(usize, usize)> {
+    let type_byte = *input.get(FRAME_TYPE_OFFSET)?;
+    let start = header_start(*input.get(2)?, type_byte);
+    let len_offset = header_len_offset(type_byte);
+    let len = decode_header_len(input.get(len_offset..start)?, Endianness::Le)?;
+
+    Some((start, len))
+}
+
+/// Checks the parts of a frame that can be checked without decoding it: the
+/// start and end markers, the version, the frame type and the header length
+/// and terminator. The header length is read in `endian` byte order.
+///
+/// # Return value
+///
+/// Where the header is in `input`, so its end is the offset of the header
+/// terminator
+pub(crate) fn check_envelope(
+    input: &[u8],
+    endian: Endianness,
+) -> Result<core::ops::Range<usize>, ParseError> {
+    if input.len() < MIN_FRAME_SIZE {
+        return Err(ParseError::TooShort);
+    }
+
+    if input[0] != SOH || input[1] != STX {
+        return Err(ParseError::BadStartMarker);
+    }
+
+    let size =
+        header_len_size(input[2]).ok_or(ParseError::UnsupportedVersion { found: input[2] })?;
+
+    let found = input[FRAME_TYPE_OFFSET];
+    if FrameType::from_u8(found & !SEQ_FLAG).is_none() {
+        return Err(ParseError::UnknownFrameType { found });
+    }
+
+    let end = input[input.len() - 1];
+    if end != EOT {
+        return Err(ParseError::BadEndMarker { found: end });
+    }
+
+    let len_offset = header_len_offset(found);
+    let header_start = len_offset + size;
+    let header_len = input
+        .get(len_offset..header_start)
+        .ok_or(ParseError::TooShort)?;
+    let header_end = decode_header_len(header_len, endian)
+        .and_then(|len| header_start.checked_add(len))
+        .filter(|&end| end <= input.len())
+        .ok_or(ParseError::HeaderLengthOverflow)?;
+
+    if input.get(header_end) != Some(&NUL) {
+        return Err(ParseError::MissingHeaderTerminator);
+    }
+
+    Ok(header_start..header_end)
+}
+
+/// The body and trailer headers of a frame, as found by [`locate_sections`].
+pub(crate) struct Sections<'a> {
+    /// The escaped body, or `None` if the frame has no body section.
+    pub(crate) body: Option<&'a [u8]>,
+    /// The escaped trailer headers, empty if there are none.
+    pub(crate) trailers: &'a [u8],
+    /// The offset of the terminator the checksum follows.
+    pub(crate) end: usize,
+}
+
+/// Finds the body and trailer sections of a frame that passed
+/// [`check_envelope`].
+///
+/// The checksum never holds a raw 0x3, so an `ETX` after a terminator starts
+/// a section: `ETX STX` the body, directly after the header, and `ETX SOH`
+/// the trailer headers, after the body if there is one.
+pub(crate) fn locate_sections(input: &[u8], header_end: usize) -> Result<Sections<'_>, ParseError> {
+    let mut sections = Sections {
+        body: None,
+        trailers: &[],
+        end: header_end,
+    };
+
+    if input.get(header_end + 1) == Some(&ETX) && input.get(header_end + 2) != Some(&SOH) {
+        if input.get(header_end + 2) != Some(&STX) {
+            return Err(ParseError::MissingBodyMarker);
+        }
+        let (body, end) = section_at(input, header_end + 3)?;
+        sections.body = Some(body);
+        sections.end = end;
+    }
+
+    if input.get(sections.end + 1) == Some(&ETX) {
+        if input.get(sections.end + 2) != Some(&SOH) {
+            return Err(ParseError::MissingTrailerMarker);
+        }
+        let (trailers, end) = section_at(input, sections.end + 3)?;
+        sections.trailers = trailers;
+        sections.end = end;
+    }
+
+    Ok(sections)
+}
+
+/// The escaped content of the section starting at `start`, and the offset of
+/// its terminator.
+///
+/// Content never holds a raw 0x0, so the section ends at the first one. The
+/// end marker is never a terminator, so it is left out of the search.
+pub(crate) fn section_at(input: &[u8], start: usize) -> Result<(&[u8], usize), ParseError> {
+    let len = input
+        .get(start..input.len() - 1)
+        .and_then(|rest| rest.iter().position(|&a| a == NUL))
+        .ok_or(ParseError::MissingChecksum)?;
+
+    Ok((&input[start..start + len], start + len))
+}
+
+/// Converts a u16 to array of 2 u8s corresponding to the lower and upper 8
+/// bits respectively, i.e. little-endian as every length on the wire is
+///
+/// # Arguments
+///
+/// * `input` - The u16 input that will be split.
+///
+/// # Return value
+///
+/// A array of exactly 2 u8s which correspond to the lower and upper 8 bits of
+/// `input`, in that order, so little-endian
+pub(crate) const fn u16_to_u8s(input: u16) -> [u8; 2] {
+    [
+        (input & (u8::MAX as u16)) as u8,
+        ((input >> 8) & (u8::MAX as u16)) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use heapless::Vec;
+
+    use super::{
+        check_envelope, decode_header_len, encode_header_len, escaped_len, frame_len,
+        write_escaped, Endianness, Unescape, ESCAPE, LONG_HEADER_VERSION, PROTOCOL_VERSION,
+    };
+    use crate::ParseError;
+
+    #[test]
+    fn test_escaping() {
+        let input = [b'a', 0x0, 0x1, 0x2, 0x3, 0x4, ESCAPE, b'z'];
+        assert_eq!(escaped_len(&input), input.len() + 6);
+
+        let mut escaped: Vec<u8, 16> = Vec::new();
+        write_escaped(&mut escaped, &input).unwrap();
+        assert_eq!(escaped.len(), escaped_len(&input));
+        assert!(escaped[1..escaped.len() - 1].iter().all(|&b| b > 0x4));
+
+        let unescaped: Result<Vec<u8, 16>, _> = Unescape::new(&escaped).collect();
+        assert_eq!(unescaped.as_deref(), Ok(&input[..]));
+        assert_eq!(
+            Unescape::new(&[ESCAPE]).next(),
+            Some(Err(ParseError::BadEscape))
+        );
+    }
+
+    #[test]
+    fn test_header_len() {
+        assert_eq!(
+            encode_header_len(0x1234),
+            (PROTOCOL_VERSION, [0x34, 0x12, 0, 0], 2)
+        );
+        assert_eq!(
+            encode_header_len(0x10000),
+            (LONG_HEADER_VERSION, [0, 0, 1, 0], 4)
+        );
+        assert_eq!(
+            decode_header_len(&[0x34, 0x12], Endianness::Le),
+            Some(0x1234)
+        );
+        assert_eq!(
+            decode_header_len(&[0x12, 0x34], Endianness::Be),
+            Some(0x1234)
+        );
+    }
+
+    #[test]
+    fn test_envelope() {
+        let frame = [1, 2, 2, 0, 1, 0, b'a', 0, 0xff, 0xff, 4];
+        assert_eq!(frame_len(&frame), Some(frame.len()));
+        assert_eq!(frame_len(&frame[..7]), None);
+        assert_eq!(check_envelope(&frame, Endianness::Le), Ok(6..7));
+
+        let mut long = frame;
+        long[4] = 9;
+        assert_eq!(
+            check_envelope(&long, Endianness::Le),
+            Err(ParseError::HeaderLengthOverflow)
+        );
+        assert_eq!(
+            check_envelope(&frame[..frame.len() - 1], Endianness::Le),
+            Err(ParseError::BadEndMarker { found: 0xff })
+        );
+    }
+}
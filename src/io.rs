@@ -0,0 +1,199 @@
+//! Minimal byte sink and source traits that frames are streamed through.
+//!
+//! They mirror the shape of the `embedded-io` traits so that adapting a
+//! transport only takes a few lines. With the `embedded-io` feature,
+//! [`EmbeddedIo`] adapts any `embedded_io` reader or writer directly, and
+//! with the `std` feature [`StdIo`] does the same for `std::io`.
+//!
+//! Transports from any other IO ecosystem implement [`Read`] and [`Write`]
+//! themselves, usually by forwarding `read_exact` and `write_all` to their
+//! own calls:
+//!
+//! ```ignore
+//! impl nutp::io::Write for MyTransport {
+//!     type Error = MyError;
+//!
+//!     fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+//!         self.send(buf)
+//!     }
+//! }
+//! ```
+
+use heapless::Vec;
+
+use crate::CapacityError;
+
+/// A source that frames can be read from incrementally.
+pub trait Read {
+    type Error;
+
+    /// Fills all of `buf`, or fails.
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+/// A sink that frames can be written to incrementally.
+pub trait Write {
+    type Error;
+
+    /// Writes all of `buf`, or fails.
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error>;
+}
+
+impl<const N: usize> Write for Vec<u8, N> {
+    type Error = CapacityError;
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.extend_from_slice(buf).map_err(|_| CapacityError)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Write for alloc::vec::Vec<u8> {
+    type Error = core::convert::Infallible;
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+/// Fills the slice from the front, leaving `self` as the part not yet
+/// written to.
+impl Write for &mut [u8] {
+    type Error = CapacityError;
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        if self.len() < buf.len() {
+            return Err(CapacityError);
+        }
+
+        let (written, rest) = core::mem::take(self).split_at_mut(buf.len());
+        written.copy_from_slice(buf);
+        *self = rest;
+
+        Ok(())
+    }
+}
+
+/// Fails reads once `expired` returns `true`, for
+/// [`Message::read_from_timeout`](crate::Message::read_from_timeout).
+pub(crate) struct Deadline<'a, R, F> {
+    pub(crate) inner: &'a mut R,
+    pub(crate) expired: F,
+}
+
+/// Why a [`Deadline`] read failed.
+pub(crate) enum DeadlineError<E> {
+    Io(E),
+    Expired,
+}
+
+impl<R: Read, F: FnMut() -> bool> Read for Deadline<'_, R, F> {
+    type Error = DeadlineError<R::Error>;
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        if (self.expired)() {
+            return Err(DeadlineError::Expired);
+        }
+        self.inner.read_exact(buf).map_err(DeadlineError::Io)
+    }
+}
+
+/// A sink that only counts the bytes written to it.
+#[derive(Debug, Default)]
+pub(crate) struct Counter {
+    pub(crate) count: usize,
+}
+
+impl Write for Counter {
+    type Error = core::convert::Infallible;
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.count += buf.len();
+        Ok(())
+    }
+}
+
+/// A slice ran out of bytes before a read could be filled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnexpectedEof;
+
+impl core::fmt::Display for UnexpectedEof {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("unexpected end of input")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UnexpectedEof {}
+
+impl Read for &[u8] {
+    type Error = UnexpectedEof;
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        if self.len() < buf.len() {
+            return Err(UnexpectedEof);
+        }
+
+        let (read, rest) = self.split_at(buf.len());
+        buf.copy_from_slice(read);
+        *self = rest;
+
+        Ok(())
+    }
+}
+
+/// Adapts an `embedded_io` reader or writer to the traits in this module.
+///
+/// ```ignore
+/// message.write_to(&mut EmbeddedIo(&mut uart))?;
+/// ```
+#[cfg(feature = "embedded-io")]
+#[derive(Debug)]
+pub struct EmbeddedIo<T>(pub T);
+
+#[cfg(feature = "embedded-io")]
+impl<T: embedded_io::Write> Write for EmbeddedIo<T> {
+    type Error = T::Error;
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.0.write_all(buf)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<T: embedded_io::Read> Read for EmbeddedIo<T> {
+    type Error = embedded_io::ReadExactError<T::Error>;
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.read_exact(buf)
+    }
+}
+
+/// Adapts a `std::io` reader or writer, such as a `TcpStream`, to the traits
+/// in this module.
+///
+/// ```ignore
+/// message.write_to(&mut StdIo(&mut stream))?;
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct StdIo<T>(pub T);
+
+#[cfg(feature = "std")]
+impl<T: std::io::Write> Write for StdIo<T> {
+    type Error = std::io::Error;
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.0.write_all(buf)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: std::io::Read> Read for StdIo<T> {
+    type Error = std::io::Error;
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.read_exact(buf)
+    }
+}
@@ -0,0 +1,4610 @@
+//! Messages, their builder and the typed accessors for their headers, on top
+//! of the framing in [`frame`](crate::frame).
+
+use core::fmt::{Debug, Display, Write as _};
+
+use heapless::{FnvIndexMap, String, Vec};
+
+use crate::consts::{EOT, ETX, NUL, SOH, STX};
+use crate::frame::{
+    check_envelope, crc16_update, decode_header_len, encode_header_len, escaped_len, frame_len,
+    header_len_offset, header_len_size, header_start, locate_sections, max_escaped_len,
+    min_frame_len_for, read_byte, read_escaped, read_section, u16_to_u8s, write_escaped,
+    Endianness, FrameType, Sections, Unescape, CRC16_INIT, ESCAPE, FRAME_TYPE_OFFSET,
+    HEADER_LEN_OFFSET, LONG_HEADER_VERSION, PROTOCOL_VERSION, SEQ_FLAG,
+};
+use crate::{io, BuildError, CapacityError, EncodeError, ParseError, ReadError, MAX_BODY_SIZE};
+
+/// The header that [`MessageBuilder::method`] writes as the first line of a
+/// request.
+pub const METHOD_HEADER: &str = "Method";
+
+/// The header that [`MessageBuilder::status`] writes as the first line of a
+/// response.
+pub const STATUS_HEADER: &str = "Status";
+
+/// The header [`Message::matches_route`] reads the path of a request from.
+pub const PATH_HEADER: &str = "Path";
+
+/// The header [`Message::response`] names the media type of the body in.
+pub const CONTENT_TYPE_HEADER: &str = "Content-Type";
+
+/// Separates the `key: value` lines of a message's header block unless the
+/// builder is given another [`LineSeparator`].
+pub const HEADER_SEPARATOR: &str = "\r\n";
+
+/// How many bytes from the start of a frame that failed to decode are logged.
+#[cfg(feature = "log")]
+const LOGGED_INPUT_LEN: usize = 16;
+
+/// Logs the outcome of decoding a frame with the `log` feature: the sizes of
+/// a decoded message at debug level, or the error and the start of `input`,
+/// if the frame came from a buffer, at warn level.
+#[cfg(feature = "log")]
+fn log_decode<const H: usize, const B: usize>(
+    result: Result<&Message<H, B>, &ParseError>,
+    input: Option<&[u8]>,
+) {
+    match (result, input) {
+        (Ok(message), _) => log::debug!(
+            "decoded frame with a {} byte header and a {} byte body",
+            message.header.len(),
+            message.body_len()
+        ),
+        (Err(e), Some(input)) => log::warn!(
+            "failed to decode frame starting {:02x?}: {:?}",
+            &input[..input.len().min(LOGGED_INPUT_LEN)],
+            e
+        ),
+        (Err(e), None) => log::warn!("failed to decode frame: {:?}", e),
+    }
+}
+
+/// The line ending a [`MessageBuilder`] joins header lines with.
+///
+/// Parsing accepts either, so a message decodes the same way whichever
+/// separator it was built with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineSeparator {
+    /// `\r\n`, as [`HEADER_SEPARATOR`].
+    #[default]
+    CrLf,
+    /// A bare `\n`.
+    Lf,
+}
+
+impl LineSeparator {
+    /// The separator as it appears in the header block.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            LineSeparator::CrLf => HEADER_SEPARATOR,
+            LineSeparator::Lf => "\n",
+        }
+    }
+}
+
+/// Checks that a header line can be written and split back apart: the key is
+/// non-empty printable ASCII without a `:`, and neither part holds a line
+/// break. An empty value is fine and reads back as `""`.
+fn check_header(key: &str, value: &str) -> Result<(), BuildError> {
+    if key.is_empty() {
+        return Err(BuildError::EmptyHeaderKey);
+    }
+    if key
+        .bytes()
+        .any(|b| b == b':' || !(b' '..=b'~').contains(&b))
+    {
+        return Err(BuildError::InvalidHeaderKey);
+    }
+    if value.contains(['\r', '\n']) {
+        return Err(BuildError::InvalidHeaderValue);
+    }
+    Ok(())
+}
+
+/// Checks every line of a header block as [`check_header`], and that there
+/// are at most [`MAX_HEADERS`] of them.
+///
+/// Every constructor that takes a header block as a string goes through this,
+/// so they hold keys and values to the same rules as the builder.
+pub(crate) fn check_header_block(header: &str) -> Result<(), BuildError> {
+    for (i, line) in header_lines(header).filter(|l| !l.is_empty()).enumerate() {
+        if i == MAX_HEADERS {
+            return Err(BuildError::TooManyHeaders);
+        }
+        let (k, v) = line.split_once(": ").ok_or(BuildError::InvalidHeader)?;
+        check_header(k, v)?;
+    }
+    Ok(())
+}
+
+/// Splits a header block into `key: value` pairs sorted by key and then
+/// value, or `None` if a line has no `: ` or there are more than
+/// [`MAX_HEADERS`] lines.
+fn sorted_lines(header: &str) -> Option<Vec<(&str, &str), MAX_HEADERS>> {
+    let mut lines: Vec<(&str, &str), MAX_HEADERS> = Vec::new();
+    for line in header_lines(header).filter(|l| !l.is_empty()) {
+        lines.push(line.split_once(": ")?).ok()?;
+    }
+    lines.sort_unstable();
+    Some(lines)
+}
+
+/// Splits a header block into lines on `\n`, dropping the `\r` of a `\r\n`.
+fn header_lines(header: &str) -> impl Iterator<Item = &str> {
+    header
+        .split('\n')
+        .map(|l| l.strip_suffix('\r').unwrap_or(l))
+}
+
+/// The bytes of a header name in ASCII lowercase, for ordering names the way
+/// lookups compare them.
+fn lowercase_bytes(key: &str) -> impl Iterator<Item = u8> + '_ {
+    key.bytes().map(|b| b.to_ascii_lowercase())
+}
+
+/// The most header lines a [`MessageBuilder`] holds and a parsed header block
+/// may have, which bounds the work done on hostile frames.
+///
+/// A power of two, so it can size a [`HeaderMap`].
+pub const MAX_HEADERS: usize = 16;
+
+/// The most bytes of trailer headers a message can carry, see
+/// [`MessageBuilder::add_trailer`].
+pub const MAX_TRAILER_LEN: usize = 256;
+
+/// The headers of a message, keyed by header name.
+pub type HeaderMap = FnvIndexMap<String<32>, String<128>, MAX_HEADERS>;
+
+/// Turns the unescaped bytes of a header block into the header string,
+/// refusing blocks of more than [`MAX_HEADERS`] lines.
+fn decode_header<const H: usize>(input: &[u8]) -> Result<String<H>, ParseError> {
+    let header = core::str::from_utf8(input).map_err(|_| ParseError::InvalidUtf8)?;
+    if header_lines(header)
+        .filter(|l| !l.is_empty())
+        .nth(MAX_HEADERS)
+        .is_some()
+    {
+        return Err(ParseError::TooManyHeaders);
+    }
+    String::try_from(header).map_err(|_| ParseError::HeaderLengthOverflow)
+}
+
+/// Builds a [`Message`] whose header block holds up to `H` bytes and whose
+/// body holds up to `B` bytes.
+///
+/// Use [`MessageBuilder::new`] for the default capacities, or
+/// `MessageBuilder::<H, B>::default()` to pick them.
+///
+/// The headers are kept inline in room for [`MAX_HEADERS`] of them, so adding
+/// one never allocates or rehashes and there is no capacity to reserve.
+#[derive(Debug)]
+pub struct MessageBuilder<const H: usize = MAX_BODY_SIZE, const B: usize = MAX_BODY_SIZE> {
+    headers: Vec<(String<32>, String<128>), MAX_HEADERS>,
+    first_line: Option<String<{ METHOD_HEADER.len() + 2 + 32 }>>,
+    raw_header: Option<String<H>>,
+    body: Option<Vec<u8, B>>,
+    content_length: bool,
+    separator: LineSeparator,
+    frame_type: FrameType,
+    trailers: String<MAX_TRAILER_LEN>,
+    seq: Option<u16>,
+}
+
+impl<const H: usize, const B: usize> Default for MessageBuilder<H, B> {
+    fn default() -> Self {
+        Self {
+            headers: Vec::new(),
+            first_line: None,
+            raw_header: None,
+            body: None,
+            content_length: false,
+            separator: LineSeparator::CrLf,
+            frame_type: FrameType::Request,
+            trailers: String::new(),
+            seq: None,
+        }
+    }
+}
+
+impl MessageBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<const H: usize, const B: usize> MessageBuilder<H, B> {
+    /// Adds a `key: value` header line.
+    ///
+    /// Keys hold up to 32 bytes and values up to 128 bytes, so a single line
+    /// is at most 162 bytes once joined.
+    ///
+    /// The key is stored and sent exactly as given; lookups such as
+    /// [`Message::header_value`] ignore ASCII case instead. Headers are kept
+    /// in a `Vec`, so [`MessageBuilder::build`] writes them in the order they
+    /// were added.
+    ///
+    /// Values are refused rather than escaped if they hold a `\r` or `\n`,
+    /// since either could end the line early and smuggle in another header
+    /// when the value comes from untrusted input. Any other byte is allowed.
+    ///
+    /// # Return value
+    ///
+    /// The builder, or `None` if the builder is full, already has a header
+    /// named `key` (ignoring ASCII case), or the header is malformed as
+    /// described in [`MessageBuilder::try_add_header`]. Use
+    /// [`MessageBuilder::append_header`] for headers that may repeat
+    pub fn add_header(self, key: String<32>, value: String<128>) -> Option<Self> {
+        if self
+            .headers
+            .iter()
+            .any(|(k, _)| k.eq_ignore_ascii_case(&key))
+        {
+            return None;
+        }
+
+        self.append_header(key, value)
+    }
+
+    /// Adds a `key: value` header line even if `key` is already present.
+    ///
+    /// Every occurrence is written as its own line by
+    /// [`MessageBuilder::build`], in the order they were added.
+    ///
+    /// # Return value
+    ///
+    /// The builder, or `None` if the builder is full or the header is
+    /// malformed
+    pub fn append_header(mut self, key: String<32>, value: String<128>) -> Option<Self> {
+        check_header(&key, &value).ok()?;
+        self.headers.push((key, value)).ok()?;
+        Some(self)
+    }
+
+    /// Adds a `key: value` header line like [`MessageBuilder::add_header`],
+    /// but checks up front that [`MessageBuilder::build`] will have room for
+    /// it.
+    ///
+    /// # Return value
+    ///
+    /// The builder, or a [`BuildError`] saying why the header was refused:
+    /// [`BuildError::EmptyHeaderKey`] if `key` is empty,
+    /// [`BuildError::InvalidHeaderKey`] if it has a `:`, a control byte or a
+    /// non-ASCII byte, [`BuildError::InvalidHeaderValue`] if `value` has a
+    /// `\r` or `\n`, or otherwise because the header block already has
+    /// [`MAX_HEADERS`] lines counting any method, status or raw header lines,
+    /// `key` is already present, or the block would no longer fit in `H`
+    /// bytes.
+    /// An empty `value` is allowed
+    pub fn try_add_header(self, key: String<32>, value: String<128>) -> Result<Self, BuildError> {
+        check_header(&key, &value)?;
+
+        if self
+            .headers
+            .iter()
+            .any(|(k, _)| k.eq_ignore_ascii_case(&key))
+        {
+            return Err(BuildError::DuplicateHeader);
+        }
+
+        if self.line_count() >= MAX_HEADERS {
+            return Err(BuildError::TooManyHeaders);
+        }
+
+        let len = self.headers_len();
+        let separator = if len > 0 {
+            self.separator.as_str().len()
+        } else {
+            0
+        };
+        if len + separator + key.len() + 2 + value.len() > H {
+            return Err(BuildError::HeaderTooLarge {
+                index: self.headers.len(),
+            });
+        }
+
+        self.append_header(key, value)
+            .ok_or(BuildError::TooManyHeaders)
+    }
+
+    /// Adds every `key: value` pair from `headers` in order, as
+    /// [`MessageBuilder::try_add_header`].
+    ///
+    /// There is no `FromIterator` impl to collect headers into a builder,
+    /// since it could only panic on a header that is refused.
+    ///
+    /// # Return value
+    ///
+    /// The builder, or the error for the first header that was refused
+    pub fn extend_headers<I>(mut self, headers: I) -> Result<Self, BuildError>
+    where
+        I: IntoIterator<Item = (String<32>, String<128>)>,
+    {
+        for (key, value) in headers {
+            self = self.try_add_header(key, value)?;
+        }
+        Ok(self)
+    }
+
+    /// The value of the header named `key` for updating in place, adding the
+    /// header with an empty value first if there is none, like
+    /// `HashMap::entry(key).or_default()`.
+    ///
+    /// Names are matched ignoring ASCII case as in
+    /// [`MessageBuilder::add_header`], and a header that is already present
+    /// keeps the spelling it was added with. The value is checked again by
+    /// [`MessageBuilder::build`], since it can be changed to anything here.
+    ///
+    /// # Return value
+    ///
+    /// The value of the first header named `key`,
+    /// [`BuildError::EmptyHeaderKey`] or [`BuildError::InvalidHeaderKey`] if
+    /// a new header would be malformed, or [`BuildError::TooManyHeaders`] if
+    /// there is no room for one
+    pub fn header_entry(&mut self, key: String<32>) -> Result<&mut String<128>, BuildError> {
+        let existing = self
+            .headers
+            .iter()
+            .position(|(k, _)| k.eq_ignore_ascii_case(&key));
+
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                check_header(&key, "")?;
+                self.headers
+                    .push((key, String::new()))
+                    .map_err(|_| BuildError::TooManyHeaders)?;
+                self.headers.len() - 1
+            }
+        };
+
+        Ok(&mut self.headers[index].1)
+    }
+
+    /// Removes every header named `key`, ignoring ASCII case as
+    /// [`MessageBuilder::add_header`] does, keeping the order of the rest.
+    ///
+    /// Lines written by [`MessageBuilder::method`],
+    /// [`MessageBuilder::status`] and [`MessageBuilder::set_raw_header`] are
+    /// not headers of the builder and are left alone.
+    ///
+    /// # Return value
+    ///
+    /// Whether a header was removed
+    pub fn remove_header(&mut self, key: &str) -> bool {
+        let count = self.headers.len();
+        self.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        self.headers.len() != count
+    }
+
+    /// The headers added so far as `(key, value)` pairs, in the order
+    /// [`MessageBuilder::build`] writes them.
+    ///
+    /// Lines written by [`MessageBuilder::method`],
+    /// [`MessageBuilder::status`] and [`MessageBuilder::set_raw_header`] are
+    /// not included.
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Looks up a header added so far, matching names ASCII
+    /// case-insensitively as [`Message::header_value`] does.
+    ///
+    /// # Return value
+    ///
+    /// The value of the first header named `key`, or `None` if there is none
+    pub fn get_header(&self, key: &str) -> Option<&str> {
+        self.headers()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+
+    /// The number of headers added so far, not counting a raw header block.
+    pub fn header_count(&self) -> usize {
+        self.headers.len()
+    }
+
+    /// The number of lines [`MessageBuilder::build`] would write, counting a
+    /// [`MessageBuilder::method`] or [`MessageBuilder::status`] line and the
+    /// lines of a raw header block, which a parser holds to [`MAX_HEADERS`].
+    fn line_count(&self) -> usize {
+        let raw = self
+            .raw_header
+            .as_deref()
+            .map_or(0, |raw| header_lines(raw).filter(|l| !l.is_empty()).count());
+        usize::from(self.first_line.is_some()) + raw + self.headers.len()
+    }
+
+    /// The length in bytes of the header block [`MessageBuilder::build`]
+    /// would write for the headers added so far, including any raw header
+    /// block and the separators between lines.
+    pub fn headers_len(&self) -> usize {
+        let mut len = self.first_line.as_ref().map_or(0, |l| l.len());
+        if let Some(raw) = self.raw_header.as_ref().filter(|h| !h.is_empty()) {
+            if len > 0 {
+                len += self.separator.as_str().len();
+            }
+            len += raw.len();
+        }
+        for (k, v) in &self.headers {
+            if len > 0 {
+                len += self.separator.as_str().len();
+            }
+            len += k.len() + 2 + v.len();
+        }
+        len
+    }
+
+    /// Adds a header from string slices, as [`MessageBuilder::add_header`].
+    ///
+    /// # Return value
+    ///
+    /// The builder, or [`CapacityError`] if `key` is longer than 32 bytes,
+    /// `value` is longer than 128 bytes, or [`MessageBuilder::add_header`]
+    /// refuses the header because the builder is full, `key` is already
+    /// present or the header is malformed
+    pub fn add_header_str(self, key: &str, value: &str) -> Result<Self, CapacityError> {
+        let key = String::try_from(key).map_err(|_| CapacityError)?;
+        let value = String::try_from(value).map_err(|_| CapacityError)?;
+
+        self.add_header(key, value).ok_or(CapacityError)
+    }
+
+    /// Sets the body from a string slice.
+    ///
+    /// # Return value
+    ///
+    /// The builder, or [`CapacityError`] if `body` is longer than `B` bytes
+    pub fn set_body_str(mut self, body: &str) -> Result<Self, CapacityError> {
+        self.body = Some(Vec::from_slice(body.as_bytes()).map_err(|_| CapacityError)?);
+        Ok(self)
+    }
+
+    pub fn set_body(mut self, body: String<B>) -> Self {
+        self.body = Some(body.into_bytes());
+        self
+    }
+
+    /// Sets a body of arbitrary bytes, which is sent verbatim.
+    pub fn set_body_bytes(mut self, body: Vec<u8, B>) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    /// Appends `s` to the body, starting one if there is none yet, so a body
+    /// can be written in pieces.
+    ///
+    /// # Return value
+    ///
+    /// [`CapacityError`] if the body would be longer than `B` bytes, in which
+    /// case the body is left as it was
+    pub fn push_body_str(&mut self, s: &str) -> Result<(), CapacityError> {
+        // Checked first so a failed push doesn't start an empty body
+        if self.body.as_ref().map_or(0, |b| b.len()) + s.len() > B {
+            return Err(CapacityError);
+        }
+
+        self.body
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(s.as_bytes())
+            .map_err(|_| CapacityError)
+    }
+
+    /// Removes all headers and the body so the builder can be reused.
+    ///
+    /// The header and body storage is inline and fixed-size, so its capacity
+    /// is kept and nothing is reallocated. Settings such as
+    /// [`MessageBuilder::with_content_length`] are kept too.
+    pub fn clear(&mut self) {
+        self.headers.clear();
+        self.first_line = None;
+        self.raw_header = None;
+        self.body = None;
+        self.trailers.clear();
+        self.seq = None;
+    }
+
+    /// Makes the message a request by writing a `Method: method` line first
+    /// in the header block, replacing any earlier [`MessageBuilder::method`]
+    /// or [`MessageBuilder::status`].
+    ///
+    /// # Return value
+    ///
+    /// The builder, [`BuildError::InvalidHeaderValue`] if `method` has a `\r`
+    /// or `\n` that would start another line, or
+    /// [`BuildError::HeaderTooLarge`] with index 0 if it is longer than 32
+    /// bytes
+    pub fn method(mut self, method: &str) -> Result<Self, BuildError> {
+        check_header(METHOD_HEADER, method)?;
+
+        let mut line = String::new();
+        write!(line, "{}: {}", METHOD_HEADER, method)
+            .map_err(|_| BuildError::HeaderTooLarge { index: 0 })?;
+        self.first_line = Some(line);
+        Ok(self)
+    }
+
+    /// Makes the message a response by writing a `Status: status` line first
+    /// in the header block, replacing any earlier [`MessageBuilder::method`]
+    /// or [`MessageBuilder::status`].
+    pub fn status(mut self, status: u16) -> Self {
+        let mut line = String::new();
+        // Can't fail, a u16 has at most 5 digits
+        let _ = write!(line, "{}: {}", STATUS_HEADER, status);
+        self.first_line = Some(line);
+        self
+    }
+
+    /// Starts the header block with `header` as is, for forwarding a header
+    /// block from elsewhere without splitting it into headers first.
+    ///
+    /// The block is not checked, so the caller is responsible for it being
+    /// `key: value` lines joined by the builder's [`LineSeparator`]. Headers added to
+    /// the builder are appended after it, a [`MessageBuilder::method`] or
+    /// [`MessageBuilder::status`] line goes before it, and
+    /// [`MessageBuilder::with_content_length`] does not look inside it.
+    pub fn set_raw_header(mut self, header: String<H>) -> Self {
+        self.raw_header = Some(header);
+        self
+    }
+
+    /// Adds a `key: value` trailer header, which is sent after the body for
+    /// values only known once the body is done, such as a hash of it.
+    ///
+    /// Keys and values are checked as in [`MessageBuilder::try_add_header`],
+    /// and the lines are joined with the builder's [`LineSeparator`] as they
+    /// are added. Read them back with [`Message::trailer_value`].
+    ///
+    /// # Return value
+    ///
+    /// The builder, [`BuildError::InvalidHeaderKey`] or
+    /// [`BuildError::InvalidHeaderValue`] if the line would be malformed, or
+    /// [`BuildError::TrailerTooLarge`] if the trailer headers would be longer
+    /// than [`MAX_TRAILER_LEN`] bytes
+    pub fn add_trailer(mut self, key: &str, value: &str) -> Result<Self, BuildError> {
+        check_header(key, value)?;
+
+        let separator = if self.trailers.is_empty() {
+            ""
+        } else {
+            self.separator.as_str()
+        };
+        if self.trailers.len() + separator.len() + key.len() + 2 + value.len() > MAX_TRAILER_LEN {
+            return Err(BuildError::TrailerTooLarge);
+        }
+
+        // Can't fail, the length was checked above
+        let _ = write!(self.trailers, "{}{}: {}", separator, key, value);
+        Ok(self)
+    }
+
+    /// Sets the [`FrameType`] of the message, [`FrameType::Request`] by
+    /// default.
+    pub fn frame_type(mut self, frame_type: FrameType) -> Self {
+        self.frame_type = frame_type;
+        self
+    }
+
+    /// Gives the message a sequence number, sent in the frame ahead of the
+    /// header block so a reliable delivery layer can acknowledge frames and
+    /// drop duplicates without parsing the headers.
+    ///
+    /// Messages have no sequence number unless one is set, and their frames
+    /// leave the field out.
+    pub fn seq(mut self, seq: u16) -> Self {
+        self.seq = Some(seq);
+        self
+    }
+
+    /// Joins the header lines with `separator` instead of
+    /// [`HEADER_SEPARATOR`], for peers that expect bare `\n` line endings.
+    pub fn with_separator(mut self, separator: LineSeparator) -> Self {
+        self.separator = separator;
+        self
+    }
+
+    /// Makes [`MessageBuilder::build`] set a `Content-Length` header to the
+    /// length of the body in bytes, replacing any `Content-Length` header
+    /// that was added by hand.
+    pub fn with_content_length(mut self) -> Self {
+        self.content_length = true;
+        self
+    }
+
+    fn set_content_length(&mut self) -> Result<(), BuildError> {
+        let mut len = String::new();
+        // Can't fail, a usize has at most 20 digits
+        let _ = write!(len, "{}", self.body.as_ref().map_or(0, |b| b.len()));
+
+        match self
+            .headers
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Content-Length"))
+        {
+            Some((_, v)) => *v = len,
+            None => self
+                .headers
+                .push((String::try_from("Content-Length").unwrap(), len))
+                .map_err(|_| BuildError::TooManyHeaders)?,
+        }
+
+        Ok(())
+    }
+
+    /// Joins the headers into the header block of a [`Message`].
+    ///
+    /// # Return value
+    ///
+    /// The message, [`BuildError::HeaderTooLarge`] naming the first header
+    /// that does not fit in `H` bytes, [`BuildError::TooManyHeaders`] if the
+    /// block would have more than [`MAX_HEADERS`] lines, or
+    /// [`BuildError::InvalidHeaderValue`] if a value changed through
+    /// [`MessageBuilder::header_entry`] has a line break
+    pub fn build(mut self) -> Result<Message<H, B>, BuildError> {
+        if self.content_length {
+            self.set_content_length()?;
+        }
+
+        if self.line_count() > MAX_HEADERS {
+            return Err(BuildError::TooManyHeaders);
+        }
+
+        let separator = self.separator.as_str();
+        let mut header: String<H> = String::new();
+        for line in [self.first_line.as_deref(), self.raw_header.as_deref()]
+            .into_iter()
+            .flatten()
+            .filter(|l| !l.is_empty())
+        {
+            let too_large = |_| BuildError::HeaderTooLarge { index: 0 };
+            if !header.is_empty() {
+                header.push_str(separator).map_err(too_large)?;
+            }
+            header.push_str(line).map_err(too_large)?;
+        }
+
+        for (index, (k, v)) in self.headers.iter().enumerate() {
+            // Values may have changed through `header_entry` since they were
+            // checked
+            check_header(k, v)?;
+
+            let too_large = |_| BuildError::HeaderTooLarge { index };
+            if !header.is_empty() {
+                header.push_str(separator).map_err(too_large)?;
+            }
+            header.push_str(k).map_err(too_large)?;
+            header.push_str(": ").map_err(too_large)?;
+            header.push_str(v).map_err(too_large)?;
+        }
+
+        Ok(Message {
+            frame_type: self.frame_type,
+            header,
+            body: self.body,
+            trailers: self.trailers,
+            seq: self.seq,
+        })
+    }
+
+    /// Joins the headers like [`MessageBuilder::build`], but in order of
+    /// their keys (ignoring ASCII case) instead of the order they were
+    /// added, so builders with the same headers produce the same frame.
+    ///
+    /// Any [`MessageBuilder::method`], [`MessageBuilder::status`] or raw
+    /// header line still comes first, and a `Content-Length` added by
+    /// [`MessageBuilder::with_content_length`] comes last.
+    ///
+    /// # Return value
+    ///
+    /// The message, or [`BuildError::HeaderTooLarge`] naming the first header
+    /// in sorted order that does not fit in `H` bytes
+    pub fn build_sorted(mut self) -> Result<Message<H, B>, BuildError> {
+        self.headers.sort_unstable_by(|(ak, av), (bk, bv)| {
+            lowercase_bytes(ak)
+                .cmp(lowercase_bytes(bk))
+                .then_with(|| ak.cmp(bk))
+                .then_with(|| av.cmp(bv))
+        });
+        self.build()
+    }
+}
+
+/// A decoded message whose header block holds up to `H` bytes and whose body
+/// holds up to `B` bytes.
+///
+/// The header block of a message that was parsed from a frame, deserialized,
+/// made with [`Message::new`] or [`Message::from_parts`] or built from headers
+/// added to a [`MessageBuilder`] is made of `key: value` lines. A block given to [`MessageBuilder::set_raw_header`] is
+/// not checked; use [`Message::validate`] for it.
+///
+/// A message without a body, such as a ping, is sent without the body
+/// section and is told apart from an empty body by [`Message::has_body`].
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Message<const H: usize = MAX_BODY_SIZE, const B: usize = MAX_BODY_SIZE> {
+    pub(crate) frame_type: FrameType,
+    pub(crate) header: String<H>,
+    pub(crate) body: Option<Vec<u8, B>>,
+    pub(crate) trailers: String<MAX_TRAILER_LEN>,
+    pub(crate) seq: Option<u16>,
+}
+
+impl<const H: usize, const B: usize> Debug for Message<H, B> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_fmt(format_args!(
+            "[0x1][0x2][{:#x}][{:?}]",
+            Self::encoded_header_len(&self.header).0,
+            self.frame_type,
+        ))?;
+
+        if let Some(seq) = self.seq {
+            f.write_fmt(format_args!("[seq {}]", seq))?;
+        }
+
+        f.write_fmt(format_args!("[{}][{:?}]", self.header.len(), self.header,))?;
+
+        if let Some(body) = &self.body {
+            match core::str::from_utf8(body) {
+                Ok(body) => f.write_fmt(format_args!("[0x3][0x2][{:?}]", body))?,
+                Err(_) => f.write_fmt(format_args!("[0x3][0x2][{:?}]", body))?,
+            }
+        }
+
+        if !self.trailers.is_empty() {
+            f.write_fmt(format_args!("[0x3][0x1][{:?}]", self.trailers))?;
+        }
+
+        f.write_fmt(format_args!("[{:#06x}][0x4]", self.checksum()))
+    }
+}
+
+/// Shows the message like a text protocol would: each header line, a blank
+/// line, then the body. A body that isn't UTF-8 is summarized by its length.
+impl<const H: usize, const B: usize> Display for Message<H, B> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if !self.header.is_empty() {
+            f.write_str(&self.header)?;
+            f.write_str(HEADER_SEPARATOR)?;
+        }
+        f.write_str(HEADER_SEPARATOR)?;
+
+        match self.body_str() {
+            Ok(body) => f.write_str(body),
+            Err(_) => write!(f, "<{} bytes of binary data>", self.body().len()),
+        }
+    }
+}
+
+/// Shows the frame of a message as the bytes sent on the wire, made by
+/// [`Message::debug_hex`].
+///
+/// Each byte is two hex digits, and markers are followed by their name, such
+/// as `01(SOH)`. The version, frame type, sequence number and header length
+/// bytes are never annotated, since they may hold marker values.
+pub struct HexFrame<'a, const H: usize, const B: usize>(&'a Message<H, B>);
+
+impl<const H: usize, const B: usize> Display for HexFrame<'_, H, B> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let (_, _, len_size) = Message::<H, B>::encoded_header_len(&self.0.header);
+        let mut w = HexWriter {
+            f,
+            pos: 0,
+            raw: 2..header_len_offset(self.0.type_byte()) + len_size,
+        };
+        self.0.write_to(&mut w)
+    }
+}
+
+/// Writes bytes into a formatter for [`HexFrame`].
+struct HexWriter<'a, 'f> {
+    f: &'a mut core::fmt::Formatter<'f>,
+    pos: usize,
+    /// The bytes that are never markers.
+    raw: core::ops::Range<usize>,
+}
+
+impl io::Write for HexWriter<'_, '_> {
+    type Error = core::fmt::Error;
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        for &byte in buf {
+            if self.pos > 0 {
+                self.f.write_str(" ")?;
+            }
+            write!(self.f, "{:02x}", byte)?;
+
+            let name = match byte {
+                _ if self.raw.contains(&self.pos) => None,
+                NUL => Some("NUL"),
+                SOH => Some("SOH"),
+                STX => Some("STX"),
+                ETX => Some("ETX"),
+                EOT => Some("EOT"),
+                ESCAPE => Some("ESC"),
+                _ => None,
+            };
+            if let Some(name) = name {
+                write!(self.f, "({})", name)?;
+            }
+
+            self.pos += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Orders messages by their header block, then by their body, comparing the
+/// bytes lexicographically. A message without a body sorts before one with an
+/// empty body.
+///
+/// Messages that tie on both are ordered by frame type, trailer headers and
+/// sequence number, so the order agrees with `==`.
+impl<const H: usize, const B: usize> Ord for Message<H, B> {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.header
+            .as_str()
+            .cmp(other.header.as_str())
+            .then_with(|| self.body.as_deref().cmp(&other.body.as_deref()))
+            .then_with(|| self.frame_type.cmp(&other.frame_type))
+            .then_with(|| self.trailers.as_str().cmp(other.trailers.as_str()))
+            .then_with(|| self.seq.cmp(&other.seq))
+    }
+}
+
+impl<const H: usize, const B: usize> PartialOrd for Message<H, B> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The empty message, [`Message::ping`], whose frame is the smallest one
+/// there is.
+impl<const H: usize, const B: usize> Default for Message<H, B> {
+    fn default() -> Self {
+        Self::ping()
+    }
+}
+
+/// Parses a frame, as [`Message::from_bytes`].
+impl<const H: usize, const B: usize> TryFrom<&[u8]> for Message<H, B> {
+    type Error = ParseError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Self::from_bytes(value)
+    }
+}
+
+/// Serializes a message into a frame, as [`Message::to_bytes`].
+impl<const N: usize, const H: usize, const B: usize> TryFrom<Message<H, B>> for Vec<u8, N> {
+    type Error = EncodeError;
+
+    fn try_from(value: Message<H, B>) -> Result<Self, Self::Error> {
+        value.to_bytes()
+    }
+}
+
+/// Logs the header length, header and body without the framing markers.
+#[cfg(feature = "defmt")]
+impl<const H: usize, const B: usize> defmt::Format for Message<H, B> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Message {{ header_len: {=usize}, header: {=str}, body: {=[u8]} }}",
+            self.header.len(),
+            self.header.as_str(),
+            self.body()
+        )
+    }
+}
+
+impl<const H: usize, const B: usize> Message<H, B> {
+    /// Splits the header block back into its `key: value` pairs.
+    ///
+    /// A header that appears more than once keeps its first position and its
+    /// last value; [`Message::header_value`] returns the first value instead.
+    ///
+    /// # Return value
+    ///
+    /// The headers of the message, or `None` if a line is not a `key: value`
+    /// pair or does not fit in a [`HeaderMap`]
+    pub fn headers(&self) -> Option<HeaderMap> {
+        let mut out = HeaderMap::new();
+
+        for line in header_lines(&self.header).filter(|l| !l.is_empty()) {
+            let (k, v) = line.split_once(": ")?;
+            out.insert(String::try_from(k).ok()?, String::try_from(v).ok()?)
+                .ok()?;
+        }
+
+        Some(out)
+    }
+
+    /// Compares two messages like `==`, except that the header lines may be
+    /// in any order.
+    ///
+    /// The header lines are compared as sorted lists, so a header repeated
+    /// with different values only matches the same repeats. A header block
+    /// that can't be split into at most [`MAX_HEADERS`] `key: value` lines is
+    /// compared exactly instead.
+    pub fn eq_ignoring_header_order(&self, other: &Self) -> bool {
+        if self.frame_type != other.frame_type
+            || self.seq != other.seq
+            || self.body != other.body
+            || self.trailers != other.trailers
+        {
+            return false;
+        }
+
+        match (sorted_lines(&self.header), sorted_lines(&other.header)) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.header == other.header,
+        }
+    }
+
+    /// Looks up the value of a single header without allocating.
+    ///
+    /// Header names are matched ASCII case-insensitively, so `content-type`
+    /// finds a header added as `Content-Type`.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The name of the header to look up.
+    ///
+    /// # Return value
+    ///
+    /// The value of the first header named `key`, or `None` if there is none
+    pub fn header_value(&self, key: &str) -> Option<&str> {
+        self.header_iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+
+    /// The raw trailer headers added with [`MessageBuilder::add_trailer`],
+    /// empty if there are none.
+    pub fn trailers(&self) -> &str {
+        &self.trailers
+    }
+
+    /// Looks up a trailer header by name, matched ASCII case-insensitively as
+    /// in [`Message::header_value`].
+    ///
+    /// # Return value
+    ///
+    /// The value of the first trailer header named `key`, or `None` if there
+    /// is none
+    pub fn trailer_value(&self, key: &str) -> Option<&str> {
+        header_lines(&self.trailers)
+            .filter_map(|line| line.split_once(": "))
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+
+    /// The method of a request made with [`MessageBuilder::method`].
+    ///
+    /// # Return value
+    ///
+    /// The value of the first header line if it is a `Method` header, or
+    /// `None` otherwise
+    pub fn method(&self) -> Option<&str> {
+        self.header_iter()
+            .next()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(METHOD_HEADER))
+            .map(|(_, v)| v)
+    }
+
+    /// Whether the message is a request for `method` on `path` or anything
+    /// under it, for dispatching requests in a router.
+    ///
+    /// The method is read as [`Message::method`] and compared exactly. The
+    /// path is the value of the [`PATH_HEADER`] and matches if it is `path`
+    /// or continues it with a `/`, so `/users` matches `/users/7` but not
+    /// `/usersettings`.
+    pub fn matches_route(&self, method: &str, path: &str) -> bool {
+        if self.method() != Some(method) {
+            return false;
+        }
+
+        self.header_value(PATH_HEADER)
+            .and_then(|p| p.strip_prefix(path))
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || path.ends_with('/'))
+    }
+
+    /// The status of a response made with [`MessageBuilder::status`].
+    ///
+    /// # Return value
+    ///
+    /// The value of the first header line if it is a numeric `Status`
+    /// header, or `None` otherwise
+    pub fn status(&self) -> Option<u16> {
+        self.header_iter()
+            .next()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(STATUS_HEADER))
+            .and_then(|(_, v)| v.parse().ok())
+    }
+
+    /// Compares the value of a header against `expected` in constant time,
+    /// for checking secrets such as auth tokens.
+    ///
+    /// Whether the header is present and the length of its value are not
+    /// hidden; only the contents of the value are compared without
+    /// short-circuiting.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The name of the header, matched as in
+    ///   [`Message::header_value`].
+    /// * `expected` - The value the header should have.
+    pub fn header_eq_ct(&self, key: &str, expected: &[u8]) -> bool {
+        let Some(value) = self.header_value(key) else {
+            return false;
+        };
+        let value = value.as_bytes();
+
+        let diff = value
+            .iter()
+            .zip(expected)
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+
+        core::hint::black_box(diff) == 0 && value.len() == expected.len()
+    }
+
+    /// Iterates over the `key: value` pairs of the header block in order,
+    /// splitting one line per step.
+    ///
+    /// Lines without a `": "` are skipped, and an empty header block yields
+    /// nothing.
+    pub fn header_iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        header_lines(&self.header).filter_map(|line| line.split_once(": "))
+    }
+
+    /// Makes a message from a single `key: value` header line and a text
+    /// body, without going through a [`MessageBuilder`].
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - The header line, or `""` for no headers.
+    /// * `body` - The body.
+    ///
+    /// # Return value
+    ///
+    /// The message, [`BuildError::InvalidHeader`] if `header` is not a single
+    /// `key: value` line, [`BuildError::EmptyHeaderKey`] or
+    /// [`BuildError::InvalidHeaderKey`] if its key would be refused by
+    /// [`MessageBuilder::try_add_header`], [`BuildError::HeaderTooLarge`] if
+    /// it does not fit in `H` bytes or [`BuildError::BodyTooLarge`] if `body`
+    /// does not fit in `B` bytes
+    pub fn new(header: &str, body: &str) -> Result<Self, BuildError> {
+        if header.contains(['\r', '\n']) {
+            return Err(BuildError::InvalidHeader);
+        }
+        check_header_block(header)?;
+
+        Ok(Message {
+            frame_type: FrameType::Request,
+            header: String::try_from(header)
+                .map_err(|_| BuildError::HeaderTooLarge { index: 0 })?,
+            body: Some(Vec::from_slice(body.as_bytes()).map_err(|_| BuildError::BodyTooLarge)?),
+            trailers: String::new(),
+            seq: None,
+        })
+    }
+
+    /// Makes a [`FrameType::Response`] with the usual shape: a `Status` line
+    /// as [`MessageBuilder::status`] writes it, a `Content-Type` header and a
+    /// text body.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The status code.
+    /// * `content_type` - The media type of `body`, such as `text/plain`.
+    /// * `body` - The body.
+    ///
+    /// # Return value
+    ///
+    /// The response, [`BuildError::InvalidHeaderValue`] if `content_type`
+    /// has a line break, [`BuildError::HeaderTooLarge`] if it is longer than
+    /// 128 bytes or does not fit in `H` bytes, or
+    /// [`BuildError::BodyTooLarge`] if `body` does not fit in `B` bytes
+    pub fn response(status: u16, content_type: &str, body: &str) -> Result<Self, BuildError> {
+        let value =
+            String::try_from(content_type).map_err(|_| BuildError::HeaderTooLarge { index: 0 })?;
+
+        MessageBuilder::<H, B>::default()
+            .frame_type(FrameType::Response)
+            .status(status)
+            // Fits, the name is a constant shorter than 32 bytes
+            .try_add_header(String::try_from(CONTENT_TYPE_HEADER).unwrap(), value)?
+            .set_body_str(body)
+            .map_err(|_| BuildError::BodyTooLarge)?
+            .build()
+    }
+
+    /// A keep-alive message of type [`FrameType::Ping`] with no headers and
+    /// no body, which is sent as the smallest possible frame.
+    pub const fn ping() -> Self {
+        Message {
+            frame_type: FrameType::Ping,
+            header: String::new(),
+            body: None,
+            trailers: String::new(),
+            seq: None,
+        }
+    }
+
+    /// Whether this is a [`Message::ping`]: a [`FrameType::Ping`] with no
+    /// headers and no body.
+    ///
+    /// A message with an empty body is not a ping.
+    pub fn is_ping(&self) -> bool {
+        self.frame_type == FrameType::Ping && self.header.is_empty() && self.body.is_none()
+    }
+
+    /// What the frame is for.
+    pub fn frame_type(&self) -> FrameType {
+        self.frame_type
+    }
+
+    /// The sequence number set with [`MessageBuilder::seq`], or `None` if the
+    /// frame has none.
+    pub fn seq(&self) -> Option<u16> {
+        self.seq
+    }
+
+    /// The frame type byte as sent, with [`SEQ_FLAG`] set if a sequence
+    /// number follows it.
+    fn type_byte(&self) -> u8 {
+        let flag = if self.seq.is_some() { SEQ_FLAG } else { 0 };
+        self.frame_type as u8 | flag
+    }
+
+    /// Takes the message apart into its header block and body, without
+    /// copying either. The [`FrameType`], the sequence number and any
+    /// trailer headers are dropped.
+    pub fn into_parts(self) -> (String<H>, Option<Vec<u8, B>>) {
+        (self.header, self.body)
+    }
+
+    /// Puts a [`FrameType::Request`] back together from the parts returned by
+    /// [`Message::into_parts`], without copying either.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - `key: value` lines joined by a [`LineSeparator`], or
+    ///   empty for no headers.
+    /// * `body` - The body, or `None` for a frame without a body section.
+    ///
+    /// # Return value
+    ///
+    /// The message, [`BuildError::InvalidHeader`],
+    /// [`BuildError::InvalidHeaderKey`] or [`BuildError::InvalidHeaderValue`]
+    /// if a line of `header` is not a valid `key: value` pair, or [`BuildError::TooManyHeaders`] if it has more
+    /// than [`MAX_HEADERS`] lines
+    pub fn from_parts(header: String<H>, body: Option<Vec<u8, B>>) -> Result<Self, BuildError> {
+        check_header_block(&header)?;
+
+        Ok(Message {
+            frame_type: FrameType::Request,
+            header,
+            body,
+            trailers: String::new(),
+            seq: None,
+        })
+    }
+
+    /// Checks that the header block is made of well-formed `key: value`
+    /// lines, for messages whose header came from elsewhere such as
+    /// [`MessageBuilder::set_raw_header`].
+    ///
+    /// The capacities need no checking, since `H` and `B` bound them.
+    ///
+    /// # Return value
+    ///
+    /// [`ParseError::InvalidHeader`] if a line is not a `key: value` pair
+    /// that [`MessageBuilder::try_add_header`] would accept, or
+    /// [`ParseError::TooManyHeaders`] if there are more than
+    /// [`MAX_HEADERS`] lines
+    pub fn validate(&self) -> Result<(), ParseError> {
+        check_header_block(&self.header).map_err(|e| match e {
+            BuildError::TooManyHeaders => ParseError::TooManyHeaders,
+            _ => ParseError::InvalidHeader,
+        })
+    }
+
+    /// Replaces the body, keeping the header block as it is.
+    pub fn with_body(mut self, body: String<B>) -> Self {
+        self.body = Some(body.into_bytes());
+        self
+    }
+
+    /// Sets the value of the header named `key` in place, or appends a
+    /// `key: value` line if there is none. The rest of the header block is
+    /// kept as it is.
+    ///
+    /// Names are matched ASCII case-insensitively as in
+    /// [`Message::header_value`], and a replaced line keeps its original
+    /// name.
+    ///
+    /// # Return value
+    ///
+    /// The message, [`BuildError::InvalidHeaderKey`] or
+    /// [`BuildError::InvalidHeaderValue`] if `key` or `value` would break the
+    /// `key: value` line format, [`BuildError::TooManyHeaders`] if a line
+    /// would be appended to a block that already has [`MAX_HEADERS`] of them,
+    /// or [`BuildError::HeaderTooLarge`] naming the line that no longer fits
+    /// in `H` bytes
+    pub fn with_header_value(mut self, key: &str, value: &str) -> Result<Self, BuildError> {
+        check_header(key, value)?;
+
+        // Keep the line ending the block was built with.
+        let separator = match self.header.find('\n') {
+            Some(i) if !self.header[..i].ends_with('\r') => LineSeparator::Lf,
+            _ => LineSeparator::CrLf,
+        }
+        .as_str();
+        let mut header: String<H> = String::new();
+        let mut replaced = false;
+        let mut index = 0;
+
+        for (i, line) in header_lines(&self.header)
+            .filter(|l| !l.is_empty())
+            .enumerate()
+        {
+            let too_large = |_| BuildError::HeaderTooLarge { index: i };
+            if !header.is_empty() {
+                header.push_str(separator).map_err(too_large)?;
+            }
+
+            match line.split_once(": ") {
+                Some((k, _)) if !replaced && k.eq_ignore_ascii_case(key) => {
+                    header.push_str(k).map_err(too_large)?;
+                    header.push_str(": ").map_err(too_large)?;
+                    header.push_str(value).map_err(too_large)?;
+                    replaced = true;
+                }
+                _ => header.push_str(line).map_err(too_large)?,
+            }
+            index = i + 1;
+        }
+
+        if !replaced {
+            if index >= MAX_HEADERS {
+                return Err(BuildError::TooManyHeaders);
+            }
+
+            let too_large = |_| BuildError::HeaderTooLarge { index };
+            if !header.is_empty() {
+                header.push_str(separator).map_err(too_large)?;
+            }
+            header.push_str(key).map_err(too_large)?;
+            header.push_str(": ").map_err(too_large)?;
+            header.push_str(value).map_err(too_large)?;
+        }
+
+        self.header = header;
+        Ok(self)
+    }
+
+    /// The raw header block, with lines joined by a [`LineSeparator`].
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    /// The raw header block as bytes, for code that takes `&[u8]`.
+    #[inline]
+    pub fn header_bytes(&self) -> &[u8] {
+        self.header.as_bytes()
+    }
+
+    /// The body as raw bytes, the same as [`Message::body`].
+    #[inline]
+    pub fn body_bytes(&self) -> &[u8] {
+        self.body()
+    }
+
+    /// The body as raw bytes, empty if the message has no body.
+    pub fn body(&self) -> &[u8] {
+        self.body.as_deref().unwrap_or_default()
+    }
+
+    /// Whether the message has a body, even an empty one.
+    ///
+    /// This is `false` for messages built without
+    /// [`MessageBuilder::set_body`] or one of its variants, whose frames
+    /// leave out the body section.
+    pub fn has_body(&self) -> bool {
+        self.body.is_some()
+    }
+
+    /// The number of lines in the header block, 0 if it is empty.
+    pub fn header_count(&self) -> usize {
+        header_lines(&self.header).filter(|l| !l.is_empty()).count()
+    }
+
+    /// The length of the body in bytes, 0 if there is none.
+    pub fn body_len(&self) -> usize {
+        self.body().len()
+    }
+
+    /// The body as UTF-8 text.
+    pub fn body_str(&self) -> Result<&str, core::str::Utf8Error> {
+        core::str::from_utf8(self.body())
+    }
+
+    /// The body as UTF-8 text with invalid sequences replaced by U+FFFD, as
+    /// `String::from_utf8_lossy`, for showing a body that
+    /// [`Message::body_str`] rejects.
+    ///
+    /// # Return value
+    ///
+    /// The body borrowed as is if it is valid UTF-8, or a repaired copy
+    #[cfg(feature = "alloc")]
+    pub fn body_str_lossy(&self) -> alloc::borrow::Cow<'_, str> {
+        alloc::string::String::from_utf8_lossy(self.body())
+    }
+
+    /// The CRC16 of the header, the body and the trailer headers, as sent
+    /// before the end marker.
+    fn checksum(&self) -> u16 {
+        self.checksum_with(&self.header)
+    }
+
+    /// [`Message::checksum`] with `header` standing in for the message's own.
+    fn checksum_with(&self, header: &str) -> u16 {
+        let crc = crc16_update(CRC16_INIT, header.as_bytes());
+        crc16_update(crc16_update(crc, self.body()), self.trailers.as_bytes())
+    }
+
+    /// Serializes the message into a frame of at most `N` bytes.
+    ///
+    /// Stable Rust can't derive `N` from `H` and `B`, so the caller picks it;
+    /// [`FrameBuffer`](crate::FrameBuffer) fits a
+    /// [`DefaultMessage`](crate::DefaultMessage).
+    ///
+    /// # Return value
+    ///
+    /// The frame, or an [`EncodeError`] naming the part of the frame that did
+    /// not fit in `N` bytes
+    pub fn to_bytes<const N: usize>(&self) -> Result<Vec<u8, N>, EncodeError> {
+        let mut out = Vec::new();
+        let _ = out.resize(N, 0);
+        let len = self.encode_into(&mut out)?;
+        out.truncate(len);
+        Ok(out)
+    }
+
+    /// Serializes the message in a canonical form, for computing a signature
+    /// or MAC that doesn't depend on how the headers happened to be written.
+    ///
+    /// Header names are lowercased, spaces and tabs around each value are
+    /// trimmed, and the lines are sorted by name and then value, as in
+    /// [`MessageBuilder::build_sorted`], and joined as `name: value` with
+    /// [`HEADER_SEPARATOR`]. Everything else is framed as it is, so messages
+    /// that differ only in header order, name case or spacing give the same
+    /// bytes.
+    ///
+    /// # Return value
+    ///
+    /// The canonical frame, or `None` if a header line has no `:`, the
+    /// normalized header block doesn't fit in `H` bytes or the frame doesn't
+    /// fit in `N` bytes
+    pub fn canonical_bytes<const N: usize>(&self) -> Option<Vec<u8, N>> {
+        let mut lines: Vec<(&str, &str), MAX_HEADERS> = Vec::new();
+        for line in header_lines(&self.header).filter(|l| !l.is_empty()) {
+            let (key, value) = line.split_once(':')?;
+            lines.push((key, value.trim_matches([' ', '\t']))).ok()?;
+        }
+        lines.sort_unstable_by(|(ak, av), (bk, bv)| {
+            lowercase_bytes(ak)
+                .cmp(lowercase_bytes(bk))
+                .then_with(|| av.cmp(bv))
+        });
+
+        let mut header: String<H> = String::new();
+        for (key, value) in lines {
+            if !header.is_empty() {
+                header.push_str(HEADER_SEPARATOR).ok()?;
+            }
+            for c in key.chars() {
+                header.push(c.to_ascii_lowercase()).ok()?;
+            }
+            header.push_str(": ").ok()?;
+            header.push_str(value).ok()?;
+        }
+
+        let mut out = Vec::new();
+        let _ = out.resize(N, 0);
+        let mut rest = &mut out[..];
+        self.write_parts(&header, &mut rest).ok()?;
+        let len = N - rest.len();
+        out.truncate(len);
+        Some(out)
+    }
+
+    /// Serializes the message into the front of `buf`, so one buffer can be
+    /// reused for many frames and live wherever the caller wants.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The buffer the frame is written to. Bytes past the frame
+    ///   are left as they are.
+    ///
+    /// # Return value
+    ///
+    /// The number of bytes written, or an [`EncodeError`] naming the part of
+    /// the frame that did not fit in `buf`
+    pub fn encode_into(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        let total = buf.len();
+        let mut rest = buf;
+        self.write_parts(&self.header, &mut rest)
+            .map_err(|(part, _)| part)?;
+        Ok(total - rest.len())
+    }
+
+    /// Serializes the message into a heap-allocated frame, which always has
+    /// room for it.
+    #[cfg(feature = "alloc")]
+    pub fn to_vec(&self) -> alloc::vec::Vec<u8> {
+        let mut out = alloc::vec::Vec::with_capacity(self.encoded_len());
+        let Ok(()) = self.write_to(&mut out);
+        out
+    }
+
+    /// Shows the frame as hex bytes with the markers named, for comparing
+    /// frames on the wire. The frame is formatted as it is written, without
+    /// a buffer.
+    pub fn debug_hex(&self) -> HexFrame<'_, H, B> {
+        HexFrame(self)
+    }
+
+    /// The exact number of bytes [`Message::to_bytes`] and
+    /// [`Message::write_to`] produce for this message.
+    pub fn encoded_len(&self) -> usize {
+        let mut counter = io::Counter::default();
+        let Ok(()) = self.write_to(&mut counter);
+        counter.count
+    }
+
+    /// Streams the frame into `w` piece by piece, without building it in
+    /// memory first.
+    ///
+    /// # Arguments
+    ///
+    /// * `w` - The sink the frame is written to.
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> Result<(), W::Error> {
+        self.write_parts(&self.header, w).map_err(|(_, e)| e)
+    }
+
+    /// The version byte and the header length field of a frame carrying
+    /// `header`, as `encode_header_len` gives them.
+    fn encoded_header_len(header: &str) -> (u8, [u8; 4], usize) {
+        // A compile error rather than a runtime one, so `encode_header_len`
+        // never sees a length that doesn't fit in four bytes
+        const {
+            assert!(
+                max_escaped_len(H) as u64 <= u32::MAX as u64,
+                "header capacity is too large to frame"
+            )
+        };
+        encode_header_len(escaped_len(header.as_bytes()))
+    }
+
+    /// Writes the frame with `header` as its header block, tagging a failed
+    /// write with the part of the frame it was for. Taking the header apart
+    /// from `self` lets [`Message::canonical_bytes`] frame a normalized
+    /// header without copying the rest of the message.
+    fn write_parts<W: io::Write>(
+        &self,
+        header: &str,
+        w: &mut W,
+    ) -> Result<(), (EncodeError, W::Error)> {
+        let part = |part: EncodeError| move |e| (part, e);
+
+        let (version, len, size) = Self::encoded_header_len(header);
+
+        let type_byte = self.type_byte();
+        let mut preamble = [SOH, STX, version, type_byte, 0, 0, 0, 0, 0, 0];
+        let len_offset = header_len_offset(type_byte);
+        if let Some(seq) = self.seq {
+            preamble[HEADER_LEN_OFFSET..len_offset].copy_from_slice(&seq.to_le_bytes());
+        }
+        preamble[len_offset..len_offset + 4].copy_from_slice(&len);
+        w.write_all(&preamble[..len_offset + size])
+            .map_err(part(EncodeError::PreambleOverflow))?;
+
+        write_escaped(w, header.as_bytes()).map_err(part(EncodeError::HeaderOverflow))?;
+        w.write_all(&[NUL])
+            .map_err(part(EncodeError::HeaderOverflow))?;
+
+        if let Some(body) = &self.body {
+            w.write_all(&[ETX, STX])
+                .map_err(part(EncodeError::BodyOverflow))?;
+            write_escaped(w, body).map_err(part(EncodeError::BodyOverflow))?;
+            w.write_all(&[NUL])
+                .map_err(part(EncodeError::BodyOverflow))?;
+        }
+
+        if !self.trailers.is_empty() {
+            w.write_all(&[ETX, SOH])
+                .map_err(part(EncodeError::TrailerOverflow))?;
+            write_escaped(w, self.trailers.as_bytes())
+                .map_err(part(EncodeError::TrailerOverflow))?;
+            w.write_all(&[NUL])
+                .map_err(part(EncodeError::TrailerOverflow))?;
+        }
+
+        write_escaped(w, &u16_to_u8s(self.checksum_with(header)))
+            .map_err(part(EncodeError::TrailerOverflow))?;
+        w.write_all(&[EOT])
+            .map_err(part(EncodeError::TrailerOverflow))
+    }
+
+    pub fn from_bytes(input: &[u8]) -> Result<Self, ParseError> {
+        Self::from_bytes_endian(input, Endianness::Le)
+    }
+
+    /// Parses a frame as [`Message::from_bytes`], reading the header length
+    /// in `endian` byte order.
+    ///
+    /// [`Endianness::Le`] is canonical and what [`Message::from_bytes`]
+    /// reads, [`Endianness::Be`] is for peers that send the length the other
+    /// way round.
+    ///
+    /// With the `log` feature, a decoded frame is logged at debug level with
+    /// its header and body sizes, and a rejected one at warn level with the
+    /// [`ParseError`] and its first bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The frame, from its start markers to its end marker.
+    /// * `endian` - The byte order of the header length field.
+    pub fn from_bytes_endian(input: &[u8], endian: Endianness) -> Result<Self, ParseError> {
+        let result = Self::decode(input, endian);
+        #[cfg(feature = "log")]
+        log_decode(result.as_ref(), Some(input));
+        result
+    }
+
+    /// Parses a frame as [`Message::from_bytes_endian`], without logging.
+    fn decode(input: &[u8], endian: Endianness) -> Result<Self, ParseError> {
+        let message = MessageRef::from_bytes_endian(input, endian)?;
+
+        if message.header.len() > max_escaped_len(H) {
+            return Err(ParseError::HeaderLengthOverflow);
+        }
+
+        message.to_owned()
+    }
+
+    /// Parses the frame at the start of `input`, which may be followed by
+    /// more bytes such as the start of the next frame.
+    ///
+    /// # Return value
+    ///
+    /// The message and the bytes after its end marker
+    pub fn from_bytes_with_remainder(input: &[u8]) -> Result<(Self, &[u8]), ParseError> {
+        let Some(len) = frame_len(input) else {
+            if input.len() >= 2 && !input.starts_with(&[SOH, STX]) {
+                return Err(ParseError::BadStartMarker);
+            }
+            return Err(ParseError::TooShort);
+        };
+
+        let (frame, rest) = input.split_at(len);
+
+        Ok((Self::from_bytes(frame)?, rest))
+    }
+
+    /// Streams the frame into a `std::io` writer, as [`Message::write_to`].
+    #[cfg(feature = "std")]
+    pub fn write_std<W: std::io::Write>(&self, w: &mut W) -> std::io::Result<()> {
+        self.write_to(&mut io::StdIo(w))
+    }
+
+    /// Reads exactly one frame from a `std::io` reader, as
+    /// [`Message::read_from`].
+    #[cfg(feature = "std")]
+    pub fn read_std<R: std::io::Read>(r: &mut R) -> Result<Self, ReadError<std::io::Error>> {
+        Self::read_from(&mut io::StdIo(r))
+    }
+
+    /// Parses the first valid frame in `input`, skipping any bytes before it
+    /// such as noise on a link that was joined mid-stream.
+    ///
+    /// A start sequence that doesn't lead to a valid frame is skipped too, so
+    /// calling this again on the remainder drains a buffer of concatenated
+    /// frames.
+    ///
+    /// # Return value
+    ///
+    /// The message and the bytes after its end marker, or the error from the
+    /// last start sequence that was tried; [`ParseError::BadStartMarker`] if
+    /// there was none
+    pub fn from_bytes_scanning(input: &[u8]) -> Result<(Self, &[u8]), ParseError> {
+        let mut rest = input;
+        let mut error = ParseError::BadStartMarker;
+
+        while let Some(start) = Message::find_frame_start(rest) {
+            match Self::from_bytes_with_remainder(&rest[start..]) {
+                Ok(found) => return Ok(found),
+                Err(e) => error = e,
+            }
+            rest = &rest[start + 1..];
+        }
+
+        Err(error)
+    }
+
+    /// Reads exactly one frame from `r`, leaving anything after its end
+    /// marker unread.
+    ///
+    /// Errors from `r` come back as [`ReadError::Io`] and malformed frames as
+    /// [`ReadError::Parse`]. Frames are logged as in
+    /// [`Message::from_bytes_endian`] with the `log` feature, but without
+    /// their bytes, which are not kept.
+    pub fn read_from<R: io::Read>(r: &mut R) -> Result<Self, ReadError<R::Error>> {
+        let result = Self::read_frame(r);
+        #[cfg(feature = "log")]
+        match &result {
+            Ok(message) => log_decode(Ok(message), None),
+            Err(ReadError::Parse(e)) => log_decode::<H, B>(Err(e), None),
+            Err(_) => {}
+        }
+        result
+    }
+
+    /// Reads one frame as [`Message::read_from`], without logging.
+    fn read_frame<R: io::Read>(r: &mut R) -> Result<Self, ReadError<R::Error>> {
+        let mut start = [0u8; HEADER_LEN_OFFSET];
+        r.read_exact(&mut start).map_err(ReadError::Io)?;
+
+        if start[0] != SOH || start[1] != STX {
+            return Err(ParseError::BadStartMarker.into());
+        }
+
+        let size =
+            header_len_size(start[2]).ok_or(ParseError::UnsupportedVersion { found: start[2] })?;
+
+        let found = start[FRAME_TYPE_OFFSET];
+        let frame_type =
+            FrameType::from_u8(found & !SEQ_FLAG).ok_or(ParseError::UnknownFrameType { found })?;
+
+        let seq = if found & SEQ_FLAG != 0 {
+            let mut seq = [0u8; 2];
+            r.read_exact(&mut seq).map_err(ReadError::Io)?;
+            Some(u16::from_le_bytes(seq))
+        } else {
+            None
+        };
+
+        let mut header_len = [0u8; 4];
+        r.read_exact(&mut header_len[..size])
+            .map_err(ReadError::Io)?;
+        let header_len = decode_header_len(&header_len[..size], Endianness::Le)
+            .filter(|&len| len <= max_escaped_len(H))
+            .ok_or(ParseError::HeaderLengthOverflow)?;
+
+        let mut header: Vec<u8, H> = Vec::new();
+        let mut remaining = header_len;
+        while remaining > 0 {
+            let byte = read_byte(r)?;
+            remaining -= 1;
+
+            let byte = match byte {
+                ESCAPE if remaining > 0 => {
+                    remaining -= 1;
+                    read_escaped(r)?
+                }
+                ESCAPE => return Err(ParseError::BadEscape.into()),
+                byte => byte,
+            };
+            header
+                .push(byte)
+                .map_err(|_| ParseError::HeaderLengthOverflow)?;
+        }
+
+        if read_byte(r)? != NUL {
+            return Err(ParseError::MissingHeaderTerminator.into());
+        }
+
+        // The checksum never holds a raw 0x3, so it marks the start of a
+        // body or trailer section as in `locate_sections`
+        let mut next = read_byte(r)?;
+        let mut marker = if next == ETX {
+            Some(read_byte(r)?)
+        } else {
+            None
+        };
+
+        let body = if marker == Some(STX) {
+            let mut body = Vec::new();
+            read_section(r, &mut body, ParseError::BodyTooLarge)?;
+            next = read_byte(r)?;
+            marker = if next == ETX {
+                Some(read_byte(r)?)
+            } else {
+                None
+            };
+            if marker.is_some_and(|m| m != SOH) {
+                return Err(ParseError::MissingTrailerMarker.into());
+            }
+            Some(body)
+        } else {
+            None
+        };
+
+        let mut trailers: Vec<u8, MAX_TRAILER_LEN> = Vec::new();
+        match marker {
+            Some(SOH) => {
+                read_section(r, &mut trailers, ParseError::TrailerTooLarge)?;
+                next = read_byte(r)?;
+            }
+            Some(_) => return Err(ParseError::MissingBodyMarker.into()),
+            None => {}
+        }
+
+        let mut checksum = [0u8; 2];
+        for byte in checksum.iter_mut() {
+            *byte = match next {
+                ESCAPE => read_escaped(r)?,
+                EOT => return Err(ParseError::MissingChecksum.into()),
+                byte => byte,
+            };
+            next = read_byte(r)?;
+        }
+
+        if next != EOT {
+            return Err(ParseError::BadEndMarker { found: next }.into());
+        }
+
+        let expected = crc16_update(CRC16_INIT, &header);
+        let expected = crc16_update(expected, body.as_deref().unwrap_or_default());
+        let expected = crc16_update(expected, &trailers);
+        let found = u16::from_le_bytes(checksum);
+        if expected != found {
+            return Err(ParseError::ChecksumMismatch { expected, found }.into());
+        }
+
+        Ok(Self {
+            frame_type,
+            header: decode_header(&header)?,
+            body,
+            trailers: decode_header(&trailers)?,
+            seq,
+        })
+    }
+
+    /// Reads exactly one frame from `r` as [`Message::read_from`], giving up
+    /// once `expired` returns `true`, so a peer that stops mid-frame can't
+    /// stall the caller forever.
+    ///
+    /// `expired` is polled before every read from `r`, so it only helps if
+    /// `r` itself returns in bounded time, such as a UART read with its own
+    /// short timeout. Any clock works: compare a timer against a deadline
+    /// taken before the call.
+    ///
+    /// # Return value
+    ///
+    /// The message, [`ReadError::Timeout`] if `expired` returned `true`
+    /// before the frame was complete, or the errors of
+    /// [`Message::read_from`]
+    pub fn read_from_timeout<R, F>(r: &mut R, expired: F) -> Result<Self, ReadError<R::Error>>
+    where
+        R: io::Read,
+        F: FnMut() -> bool,
+    {
+        let mut r = io::Deadline { inner: r, expired };
+        Self::read_from(&mut r).map_err(|e| match e {
+            ReadError::Io(io::DeadlineError::Io(e)) => ReadError::Io(e),
+            ReadError::Io(io::DeadlineError::Expired) | ReadError::Timeout => ReadError::Timeout,
+            ReadError::Parse(e) => ReadError::Parse(e),
+        })
+    }
+}
+
+impl Message {
+    /// Validates a frame without copying its header or body out of `input`.
+    ///
+    /// See [`MessageRef`] for what the returned slices contain.
+    pub fn from_bytes_ref(input: &[u8]) -> Result<MessageRef<'_>, ParseError> {
+        MessageRef::from_bytes(input)
+    }
+
+    /// Validates a frame and returns its parts as they appear on the wire,
+    /// for tools that show what the parser saw.
+    ///
+    /// This is the same parse [`Message::from_bytes`] runs, through
+    /// [`MessageRef::from_bytes`].
+    ///
+    /// # Return value
+    ///
+    /// The header length read from the frame, the escaped header and the
+    /// escaped body, which is empty if the frame has no body section
+    pub fn parse_parts(input: &[u8]) -> Result<(usize, &[u8], &[u8]), ParseError> {
+        let frame = MessageRef::from_bytes(input)?;
+        Ok((frame.header.len(), frame.header(), frame.body()))
+    }
+
+    /// Finds the header block of a frame without decoding it, for filtering
+    /// frames by a header before parsing them.
+    ///
+    /// Only the checks of [`Message::is_valid_frame`] are run; the header is
+    /// not unescaped and the checksum is not verified.
+    ///
+    /// # Return value
+    ///
+    /// The escaped header block as it appears in `input`
+    pub fn peek_header(input: &[u8]) -> Result<&[u8], ParseError> {
+        Ok(&input[check_envelope(input, Endianness::Le)?])
+    }
+
+    /// Finds the body of a frame without decoding it, as
+    /// [`Message::peek_header`].
+    ///
+    /// # Return value
+    ///
+    /// The escaped body as it appears in `input`, empty if the frame has no
+    /// body section
+    pub fn peek_body(input: &[u8]) -> Result<&[u8], ParseError> {
+        let header = check_envelope(input, Endianness::Le)?;
+        Ok(locate_sections(input, header.end)?.body.unwrap_or_default())
+    }
+
+    /// Cheaply checks whether `input` looks like a complete frame, without
+    /// decoding the header or body or verifying the checksum.
+    ///
+    /// This runs the same marker, version and header length checks as
+    /// [`Message::from_bytes`], so any input it rejects is rejected there
+    /// too.
+    pub fn is_valid_frame(input: &[u8]) -> bool {
+        check_envelope(input, Endianness::Le).is_ok()
+    }
+
+    /// Finds the first `0x1 0x2` start sequence in `input`.
+    ///
+    /// The header, body and checksum escape the start markers, but the frame
+    /// type, sequence number and header length bytes are sent raw and can
+    /// hold the sequence too, as can line noise. A match is only a candidate
+    /// that must be confirmed with [`Message::from_bytes`], searching again
+    /// one byte further on if it fails, as [`Message::from_bytes_scanning`]
+    /// does.
+    ///
+    /// # Return value
+    ///
+    /// The offset of the start sequence, or `None` if there is none
+    pub fn find_frame_start(input: &[u8]) -> Option<usize> {
+        input.windows(2).position(|w| w == [SOH, STX])
+    }
+
+    /// The shortest a frame can be once its header length is known, for
+    /// sizing a buffer before the rest of the frame arrives. This is the
+    /// bound [`FrameDecoder::push`](crate::FrameDecoder::push) rejects frames
+    /// by.
+    ///
+    /// # Arguments
+    ///
+    /// * `header_len` - The escaped header length read from the frame.
+    /// * `seq` - Whether the frame has a sequence number.
+    ///
+    /// # Return value
+    ///
+    /// The length of the frame if it has no body or trailer headers and an
+    /// unescaped checksum, with the header length sent in 4 bytes if it
+    /// doesn't fit in 2 as [`Message::to_bytes`] does, or `None` if that
+    /// overflows a `usize`
+    pub fn min_frame_size_for_header(header_len: usize, seq: bool) -> Option<usize> {
+        let version = if header_len <= u16::MAX as usize {
+            PROTOCOL_VERSION
+        } else {
+            LONG_HEADER_VERSION
+        };
+        let type_byte = if seq { SEQ_FLAG } else { 0 };
+
+        min_frame_len_for(header_start(version, type_byte), header_len)
+    }
+}
+
+/// A validated frame that borrows its header and body from the input buffer.
+///
+/// The slices are the header and body exactly as they appear in the frame, so
+/// any marker bytes in them are still escaped. They match the decoded content
+/// whenever the content contains no bytes in `0x0..=0x4` or [`ESCAPE`]; use
+/// [`MessageRef::to_owned`] to decode them otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessageRef<'a> {
+    frame_type: FrameType,
+    header: &'a [u8],
+    body: Option<&'a [u8]>,
+    trailers: &'a [u8],
+    seq: Option<u16>,
+}
+
+impl<'a> MessageRef<'a> {
+    /// Checks the framing, escaping and checksum of `input`.
+    pub fn from_bytes(input: &'a [u8]) -> Result<Self, ParseError> {
+        Self::from_bytes_endian(input, Endianness::Le)
+    }
+
+    /// Checks `input` as [`MessageRef::from_bytes`], reading the header
+    /// length in `endian` byte order.
+    pub fn from_bytes_endian(input: &'a [u8], endian: Endianness) -> Result<Self, ParseError> {
+        let header = check_envelope(input, endian)?;
+        let Sections {
+            body,
+            trailers,
+            end: body_end,
+        } = locate_sections(input, header.end)?;
+        let header = &input[header];
+
+        let mut expected = CRC16_INIT;
+        for byte in Unescape::new(header)
+            .chain(Unescape::new(body.unwrap_or_default()))
+            .chain(Unescape::new(trailers))
+        {
+            expected = crc16_update(expected, &[byte?]);
+        }
+
+        let mut checksum = [0u8; 2];
+        let mut checksum_len = 0;
+        for byte in Unescape::new(&input[body_end + 1..input.len() - 1]) {
+            *checksum
+                .get_mut(checksum_len)
+                .ok_or(ParseError::MissingChecksum)? = byte?;
+            checksum_len += 1;
+        }
+        if checksum_len != checksum.len() {
+            return Err(ParseError::MissingChecksum);
+        }
+
+        let found = u16::from_le_bytes(checksum);
+        if expected != found {
+            return Err(ParseError::ChecksumMismatch { expected, found });
+        }
+
+        let type_byte = input[FRAME_TYPE_OFFSET];
+        // `check_envelope` found the header length after the sequence number,
+        // so both of its bytes are there
+        let seq = (type_byte & SEQ_FLAG != 0)
+            .then(|| u16::from_le_bytes([input[HEADER_LEN_OFFSET], input[HEADER_LEN_OFFSET + 1]]));
+
+        Ok(Self {
+            frame_type: FrameType::from_u8(type_byte & !SEQ_FLAG).unwrap_or_default(),
+            header,
+            body,
+            trailers,
+            seq,
+        })
+    }
+
+    /// What the frame is for, as [`Message::frame_type`].
+    pub fn frame_type(&self) -> FrameType {
+        self.frame_type
+    }
+
+    /// The sequence number of the frame, as [`Message::seq`].
+    pub fn seq(&self) -> Option<u16> {
+        self.seq
+    }
+
+    /// The header block as it appears in the frame.
+    pub fn header(&self) -> &'a [u8] {
+        self.header
+    }
+
+    /// The body as it appears in the frame, empty if the frame has no body
+    /// section.
+    pub fn body(&self) -> &'a [u8] {
+        self.body.unwrap_or_default()
+    }
+
+    /// Whether the frame has a body section, as [`Message::has_body`].
+    pub fn has_body(&self) -> bool {
+        self.body.is_some()
+    }
+
+    /// The trailer headers as they appear in the frame, empty if there are
+    /// none.
+    pub fn trailers(&self) -> &'a [u8] {
+        self.trailers
+    }
+
+    /// Decodes the header block into a heap-allocated string, without the
+    /// capacity limit of a [`Message`].
+    #[cfg(feature = "alloc")]
+    pub fn header_string(&self) -> Result<alloc::string::String, ParseError> {
+        let header = Unescape::new(self.header).collect::<Result<alloc::vec::Vec<u8>, _>>()?;
+        alloc::string::String::from_utf8(header).map_err(|_| ParseError::InvalidUtf8)
+    }
+
+    /// Decodes the body into a heap-allocated buffer, without the capacity
+    /// limit of a [`Message`].
+    #[cfg(feature = "alloc")]
+    pub fn body_vec(&self) -> Result<alloc::vec::Vec<u8>, ParseError> {
+        Unescape::new(self.body()).collect()
+    }
+
+    /// Decodes the borrowed header and body into an owned [`Message`].
+    pub fn to_owned<const H: usize, const B: usize>(&self) -> Result<Message<H, B>, ParseError> {
+        let mut header: Vec<u8, H> = Vec::new();
+        for byte in Unescape::new(self.header) {
+            header
+                .push(byte?)
+                .map_err(|_| ParseError::HeaderLengthOverflow)?;
+        }
+        let header = decode_header(&header)?;
+
+        let body = match self.body {
+            Some(raw) => {
+                let mut body = Vec::new();
+                for byte in Unescape::new(raw) {
+                    body.push(byte?).map_err(|_| ParseError::BodyTooLarge)?;
+                }
+                Some(body)
+            }
+            None => None,
+        };
+
+        let mut trailers: Vec<u8, MAX_TRAILER_LEN> = Vec::new();
+        for byte in Unescape::new(self.trailers) {
+            trailers
+                .push(byte?)
+                .map_err(|_| ParseError::TrailerTooLarge)?;
+        }
+
+        Ok(Message {
+            frame_type: self.frame_type,
+            header,
+            body,
+            trailers: decode_header(&trailers)?,
+            seq: self.seq,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::str::FromStr;
+    use std::println;
+
+    use heapless::String;
+
+    extern crate std;
+
+    #[test]
+    fn test_bytes() {
+        let message = crate::MessageBuilder::new()
+            .add_header(
+                String::from_str("Content-Type").unwrap(),
+                String::from_str("text/html").unwrap(),
+            )
+            .unwrap()
+            .set_body(String::from_str("<html><body><h1>Hello, world!</h1></body></html>").unwrap())
+            .build()
+            .unwrap();
+
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        println!("{:?}", bytes);
+        let message2 = crate::DefaultMessage::from_bytes(&bytes).unwrap();
+
+        assert_eq!(message, message2);
+    }
+
+    #[test]
+    fn test_header() {
+        let message = [
+            1, 2, 2, 0, 20, 0, 82, 101, 113, 117, 101, 115, 116, 45, 68, 97, 116, 97, 58, 32, 112,
+            104, 97, 115, 101, 115, 0, 3, 2, 0, 0x85, 0x1e, 4, 0, 0, 2,
+        ]
+        .to_vec();
+
+        let end = message.iter().position(|&a| a == 0x4).unwrap();
+
+        let message = message[..end + 1].to_vec();
+
+        let message = crate::DefaultMessage::from_bytes(&message);
+
+        assert!(message.is_ok());
+
+        assert_eq!(
+            message,
+            Ok(crate::DefaultMessage {
+                frame_type: crate::FrameType::Request,
+                header: String::from_str("Request-Data: phases").unwrap(),
+                body: Some(heapless::Vec::new()),
+                trailers: String::new(),
+                seq: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        use crate::{DefaultMessage, ParseError};
+
+        assert_eq!(
+            DefaultMessage::from_bytes(&[1, 3, 1, 0, 0, 0, 3, 2, 0, 4]),
+            Err(ParseError::BadStartMarker)
+        );
+        assert_eq!(
+            DefaultMessage::from_bytes(&[1, 2, 2, 0, 0, 0, 0, 3, 2, 0, 5]),
+            Err(ParseError::BadEndMarker { found: 5 })
+        );
+        assert_eq!(
+            DefaultMessage::from_bytes(&[1, 2, 2, 0, 0, 0, 0, 3, 3, 0, 4]),
+            Err(ParseError::MissingBodyMarker)
+        );
+        assert_eq!(
+            DefaultMessage::from_bytes(&[1, 2, 9, 0, 0, 0, 3, 2, 0, 0x33, 0x27, 4]),
+            Err(ParseError::UnsupportedVersion { found: 9 })
+        );
+    }
+
+    #[test]
+    fn test_headers_round_trip() {
+        let message = crate::MessageBuilder::new()
+            .add_header(
+                String::from_str("Content-Type").unwrap(),
+                String::from_str("text/html").unwrap(),
+            )
+            .unwrap()
+            .add_header(
+                String::from_str("Request-Data").unwrap(),
+                String::from_str("phases").unwrap(),
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            message.header(),
+            "Content-Type: text/html\r\nRequest-Data: phases"
+        );
+
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        let headers = crate::DefaultMessage::from_bytes(&bytes)
+            .unwrap()
+            .headers()
+            .unwrap();
+
+        let mut expected = crate::HeaderMap::new();
+        expected
+            .insert(
+                String::from_str("Content-Type").unwrap(),
+                String::from_str("text/html").unwrap(),
+            )
+            .unwrap();
+        expected
+            .insert(
+                String::from_str("Request-Data").unwrap(),
+                String::from_str("phases").unwrap(),
+            )
+            .unwrap();
+
+        assert_eq!(headers, expected);
+    }
+
+    #[test]
+    fn test_header_value() {
+        let message = crate::MessageBuilder::new()
+            .add_header(
+                String::from_str("Content-Type").unwrap(),
+                String::from_str("text/html").unwrap(),
+            )
+            .unwrap()
+            .add_header(
+                String::from_str("Request-Data").unwrap(),
+                String::from_str("phases").unwrap(),
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(message.header_value("Content-Type"), Some("text/html"));
+        assert_eq!(message.header_value("Request-Data"), Some("phases"));
+        assert_eq!(message.header_value("Accept"), None);
+    }
+
+    #[test]
+    fn test_header_value_ignores_case() {
+        let message = crate::MessageBuilder::new()
+            .add_header(
+                String::from_str("Content-Type").unwrap(),
+                String::from_str("text/html").unwrap(),
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(message.header_value("content-type"), Some("text/html"));
+        assert_eq!(message.header_value("CONTENT-TYPE"), Some("text/html"));
+        assert_eq!(message.header(), "Content-Type: text/html");
+    }
+
+    #[test]
+    fn test_binary_body() {
+        let body = heapless::Vec::from_slice(&[0xff, 0x80, 0xc3, 0x28, b'a']).unwrap();
+        let message = crate::MessageBuilder::new()
+            .set_body_bytes(body.clone())
+            .build()
+            .unwrap();
+
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        let message = crate::DefaultMessage::from_bytes(&bytes).unwrap();
+
+        assert_eq!(message.body(), &body[..]);
+    }
+
+    #[test]
+    fn test_escaped_markers() {
+        let body = heapless::Vec::from_slice(&[0x0, 0x1, 0x2, 0x3, 0x4, 0x1b, 0x24]).unwrap();
+        let message = crate::MessageBuilder::new()
+            .add_header(
+                String::from_str("Marker").unwrap(),
+                String::from_str("\u{2}\u{4}").unwrap(),
+            )
+            .unwrap()
+            .set_body_bytes(body.clone())
+            .build()
+            .unwrap();
+
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+
+        assert_eq!(
+            bytes.iter().filter(|&&b| b == 0x4).count(),
+            1,
+            "only the end marker should be a raw 0x4"
+        );
+        assert_eq!(crate::DefaultMessage::from_bytes(&bytes), Ok(message));
+    }
+
+    #[test]
+    fn test_nul_in_content() {
+        let message = crate::MessageBuilder::new()
+            .add_header_str("Nul", "a\0b")
+            .unwrap()
+            .set_body_bytes(heapless::Vec::from_slice(b"c\0d").unwrap())
+            .build()
+            .unwrap();
+
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        let header_end = 5 + usize::from(bytes[3]);
+        assert_eq!(
+            bytes[5..].iter().position(|&b| b == 0x0),
+            Some(header_end - 5)
+        );
+
+        let parsed = crate::DefaultMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.header_value("Nul"), Some("a\0b"));
+        assert_eq!(parsed.body(), b"c\0d");
+    }
+
+    #[test]
+    fn test_bad_escape() {
+        use crate::{DefaultMessage, ParseError};
+
+        assert_eq!(
+            DefaultMessage::from_bytes(&[1, 2, 2, 0, 0, 0, 0, 3, 2, 0x1b, b'a', 0, 4]),
+            Err(ParseError::BadEscape)
+        );
+    }
+
+    #[test]
+    fn test_checksum() {
+        use crate::{DefaultMessage, ParseError};
+
+        assert_eq!(
+            crate::frame::crc16_update(crate::CRC16_INIT, b"123456789"),
+            0x29b1
+        );
+
+        let message = crate::MessageBuilder::new()
+            .set_body(String::from_str("hello").unwrap())
+            .build()
+            .unwrap();
+        let mut bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+
+        let body_start = bytes.iter().position(|&b| b == b'h').unwrap();
+        bytes[body_start] = b'j';
+
+        assert!(matches!(
+            DefaultMessage::from_bytes(&bytes),
+            Err(ParseError::ChecksumMismatch { .. })
+        ));
+
+        let len = bytes.len();
+        bytes.remove(len - 2);
+
+        assert_eq!(
+            DefaultMessage::from_bytes(&bytes),
+            Err(ParseError::MissingChecksum)
+        );
+    }
+
+    #[test]
+    fn test_small_capacities() {
+        use crate::{DefaultMessage, Message, MessageBuilder, ParseError};
+
+        let message = MessageBuilder::<32, 8>::default()
+            .add_header(
+                String::from_str("Id").unwrap(),
+                String::from_str("7").unwrap(),
+            )
+            .unwrap()
+            .set_body(String::from_str("tiny").unwrap())
+            .build()
+            .unwrap();
+
+        let bytes: heapless::Vec<u8, 64> = message.to_bytes().unwrap();
+        assert_eq!(Message::<32, 8>::from_bytes(&bytes), Ok(message));
+
+        let large = crate::MessageBuilder::new()
+            .set_body(String::from_str("more than eight").unwrap())
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = large.to_bytes().unwrap();
+
+        assert!(DefaultMessage::from_bytes(&bytes).is_ok());
+        assert_eq!(
+            Message::<32, 8>::from_bytes(&bytes),
+            Err(ParseError::BodyTooLarge)
+        );
+    }
+
+    #[test]
+    fn test_borrowed_parse() {
+        let message = crate::MessageBuilder::new()
+            .add_header(
+                String::from_str("Content-Type").unwrap(),
+                String::from_str("text/plain").unwrap(),
+            )
+            .unwrap()
+            .set_body(String::from_str("hello").unwrap())
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+
+        let borrowed = crate::Message::from_bytes_ref(&bytes).unwrap();
+
+        assert_eq!(borrowed.header(), b"Content-Type: text/plain");
+        assert_eq!(borrowed.body(), b"hello");
+        assert_eq!(borrowed.to_owned(), Ok(message));
+    }
+
+    #[test]
+    fn test_write_to() {
+        struct Chunks(std::vec::Vec<std::vec::Vec<u8>>);
+
+        impl crate::io::Write for Chunks {
+            type Error = core::convert::Infallible;
+
+            fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+                self.0.push(buf.to_vec());
+                Ok(())
+            }
+        }
+
+        let message = crate::MessageBuilder::new()
+            .set_body(String::from_str("a\u{4}b").unwrap())
+            .build()
+            .unwrap();
+
+        let mut chunks = Chunks(std::vec::Vec::new());
+        message.write_to(&mut chunks).unwrap();
+
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        assert!(chunks.0.len() > 1);
+        assert_eq!(chunks.0.concat(), bytes.as_slice());
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn test_embedded_io() {
+        use crate::{io::EmbeddedIo, DefaultMessage};
+
+        let message = crate::MessageBuilder::new()
+            .add_header(
+                String::from_str("Content-Type").unwrap(),
+                String::from_str("text/plain").unwrap(),
+            )
+            .unwrap()
+            .set_body(String::from_str("hello").unwrap())
+            .build()
+            .unwrap();
+
+        let mut buf = [0u8; 64];
+        let mut sink = &mut buf[..];
+        message.write_to(&mut EmbeddedIo(&mut sink)).unwrap();
+        let written = 64 - sink.len();
+
+        let mut source = &buf[..written];
+        assert_eq!(
+            DefaultMessage::read_from(&mut EmbeddedIo(&mut source)),
+            Ok(message)
+        );
+    }
+
+    #[test]
+    fn test_read_from() {
+        use crate::{io::UnexpectedEof, DefaultMessage, ParseError, ReadError};
+
+        let message = crate::MessageBuilder::new()
+            .add_header(
+                String::from_str("Marker").unwrap(),
+                String::from_str("\u{1b}").unwrap(),
+            )
+            .unwrap()
+            .set_body(String::from_str("a\u{0}\u{4}b").unwrap())
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+
+        let mut stream = std::vec::Vec::new();
+        stream.extend_from_slice(&bytes);
+        stream.extend_from_slice(&bytes);
+        let mut stream = stream.as_slice();
+
+        assert_eq!(DefaultMessage::read_from(&mut stream), Ok(message.clone()));
+        assert_eq!(DefaultMessage::read_from(&mut stream), Ok(message));
+        assert_eq!(
+            DefaultMessage::read_from(&mut stream),
+            Err(ReadError::Io(UnexpectedEof))
+        );
+
+        assert_eq!(
+            DefaultMessage::read_from(&mut &bytes[..bytes.len() - 1]),
+            Err(ReadError::Io(UnexpectedEof))
+        );
+        assert_eq!(
+            DefaultMessage::read_from(&mut &[1u8, 2, 2, 0, 0, 0, 0, 3, 2, 4][..]),
+            Err(ReadError::Parse(ParseError::MissingChecksum))
+        );
+    }
+
+    #[test]
+    fn test_header_len_is_exact() {
+        use crate::{DefaultMessage, Message, ParseError};
+
+        let empty = crate::MessageBuilder::new().build().unwrap();
+        let bytes: crate::FrameBuffer = empty.to_bytes().unwrap();
+
+        assert_eq!(&bytes[4..7], &[0, 0, 0]);
+        assert_eq!(DefaultMessage::from_bytes(&bytes), Ok(empty));
+
+        let full = Message::<8, 8> {
+            frame_type: crate::FrameType::Request,
+            header: String::from_str("abcdefgh").unwrap(),
+            body: Some(heapless::Vec::new()),
+            trailers: String::new(),
+            seq: None,
+        };
+        let bytes: heapless::Vec<u8, 32> = full.to_bytes().unwrap();
+
+        assert_eq!(&bytes[4..6], &[8, 0]);
+        assert_eq!(bytes[14], 0);
+        assert_eq!(Message::<8, 8>::from_bytes(&bytes), Ok(full));
+
+        assert_eq!(
+            DefaultMessage::from_bytes(&[1, 2, 2, 0, 1, 0, 0, 3, 2, 0, 4]),
+            Err(ParseError::MissingHeaderTerminator)
+        );
+        assert_eq!(
+            DefaultMessage::from_bytes(&[1, 2, 2, 0, 1, 0, 4]),
+            Err(ParseError::MissingHeaderTerminator)
+        );
+    }
+
+    #[test]
+    fn test_header_len_over_capacity() {
+        use crate::{Message, ParseError, ReadError};
+
+        let message = Message::<32, 8> {
+            frame_type: crate::FrameType::Request,
+            header: String::from_str("abcdefghijklmnopq").unwrap(),
+            body: Some(heapless::Vec::new()),
+            trailers: String::new(),
+            seq: None,
+        };
+        let frame: heapless::Vec<u8, 64> = message.to_bytes().unwrap();
+
+        assert_eq!(
+            Message::<8, 8>::from_bytes(&frame),
+            Err(ParseError::HeaderLengthOverflow)
+        );
+        assert_eq!(
+            Message::<8, 8>::read_from(&mut &frame[..6]),
+            Err(ReadError::Parse(ParseError::HeaderLengthOverflow))
+        );
+    }
+
+    #[test]
+    fn test_header_order() {
+        let mut builder = crate::MessageBuilder::new();
+        for key in ["Zeta", "Alpha", "Mid", "Beta"] {
+            builder = builder
+                .add_header(
+                    String::from_str(key).unwrap(),
+                    String::from_str("1").unwrap(),
+                )
+                .unwrap();
+        }
+        let message = builder.build().unwrap();
+
+        assert_eq!(message.header(), "Zeta: 1\r\nAlpha: 1\r\nMid: 1\r\nBeta: 1");
+        assert!(message
+            .headers()
+            .unwrap()
+            .keys()
+            .map(|k| k.as_str())
+            .eq(["Zeta", "Alpha", "Mid", "Beta"]));
+    }
+
+    #[test]
+    fn test_duplicate_headers() {
+        let builder = crate::MessageBuilder::new()
+            .add_header(
+                String::from_str("Set-Cookie").unwrap(),
+                String::from_str("a=1").unwrap(),
+            )
+            .unwrap();
+
+        assert!(builder
+            .add_header(
+                String::from_str("set-cookie").unwrap(),
+                String::from_str("b=2").unwrap(),
+            )
+            .is_none());
+
+        let message = crate::MessageBuilder::new()
+            .add_header(
+                String::from_str("Set-Cookie").unwrap(),
+                String::from_str("a=1").unwrap(),
+            )
+            .unwrap()
+            .append_header(
+                String::from_str("Set-Cookie").unwrap(),
+                String::from_str("b=2").unwrap(),
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(message.header(), "Set-Cookie: a=1\r\nSet-Cookie: b=2");
+        assert_eq!(message.header_value("Set-Cookie"), Some("a=1"));
+    }
+
+    #[test]
+    fn test_str_helpers() {
+        use crate::{CapacityError, MessageBuilder};
+
+        let message = MessageBuilder::new()
+            .add_header_str("Content-Type", "text/plain")
+            .unwrap()
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(message.header_value("Content-Type"), Some("text/plain"));
+        assert_eq!(message.body(), b"hello");
+
+        assert_eq!(
+            MessageBuilder::new()
+                .add_header_str(&"k".repeat(33), "v")
+                .unwrap_err(),
+            CapacityError
+        );
+        assert_eq!(
+            MessageBuilder::<8, 4>::default()
+                .set_body_str("hello")
+                .unwrap_err(),
+            CapacityError
+        );
+    }
+
+    #[test]
+    fn test_build_errors() {
+        use crate::{BuildError, MessageBuilder};
+
+        let result = MessageBuilder::<16, 8>::default()
+            .add_header_str("Id", "7")
+            .unwrap()
+            .add_header_str("Name", "too long")
+            .unwrap()
+            .build();
+
+        assert_eq!(result, Err(BuildError::HeaderTooLarge { index: 1 }));
+    }
+
+    #[test]
+    fn test_utf8() {
+        use crate::{DefaultMessage, ParseError};
+
+        let message = crate::MessageBuilder::new()
+            .add_header_str("Place", "café")
+            .unwrap()
+            .set_body_str("crème brûlée")
+            .unwrap()
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        let message = DefaultMessage::from_bytes(&bytes).unwrap();
+
+        assert_eq!(message.header_value("Place"), Some("café"));
+        assert_eq!(message.body_str(), Ok("crème brûlée"));
+
+        assert_eq!(
+            DefaultMessage::from_bytes(&[1, 2, 2, 0, 1, 0, 0xff, 0, 3, 2, 0, 0x1b, 0x20, 0xff, 4]),
+            Err(ParseError::InvalidUtf8)
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        use std::format;
+
+        let message = crate::MessageBuilder::new()
+            .add_header_str("Content-Type", "text/plain")
+            .unwrap()
+            .add_header_str("Id", "7")
+            .unwrap()
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            format!("{}", message),
+            "Content-Type: text/plain\r\nId: 7\r\n\r\nhello"
+        );
+
+        let binary = crate::MessageBuilder::new()
+            .set_body_bytes(heapless::Vec::from_slice(&[0xff, 0xfe]).unwrap())
+            .build()
+            .unwrap();
+
+        assert_eq!(format!("{}", binary), "\r\n<2 bytes of binary data>");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_std_io() {
+        use crate::{DefaultMessage, ReadError};
+
+        let message = crate::MessageBuilder::new()
+            .add_header_str("Content-Type", "text/plain")
+            .unwrap()
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let mut stream = std::vec::Vec::new();
+        message.write_std(&mut stream).unwrap();
+        assert_eq!(stream.len(), message.encoded_len());
+
+        let mut cursor = std::io::Cursor::new(&stream);
+        assert_eq!(DefaultMessage::read_std(&mut cursor).unwrap(), message);
+
+        let error = DefaultMessage::read_std(&mut &stream[..4]).unwrap_err();
+        assert!(matches!(error, ReadError::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof));
+
+        let error: std::boxed::Box<dyn std::error::Error> =
+            std::boxed::Box::new(DefaultMessage::from_bytes(&stream[1..]).unwrap_err());
+        assert_eq!(
+            std::string::ToString::to_string(&error),
+            "frame does not start with 0x1 0x2"
+        );
+    }
+
+    #[test]
+    fn test_encoded_len() {
+        let messages = [
+            crate::MessageBuilder::new().build().unwrap(),
+            crate::MessageBuilder::new()
+                .add_header_str("Content-Type", "text/plain")
+                .unwrap()
+                .set_body_str("hello")
+                .unwrap()
+                .build()
+                .unwrap(),
+            crate::MessageBuilder::new()
+                .add_header_str("Escapes", "\u{1}\u{1b}")
+                .unwrap()
+                .set_body_bytes(heapless::Vec::from_slice(&[0, 1, 2, 3, 4, 0x1b]).unwrap())
+                .build()
+                .unwrap(),
+        ];
+
+        for message in messages {
+            let len = message.encoded_len();
+            let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+            assert_eq!(len, bytes.len());
+        }
+    }
+
+    #[test]
+    fn test_content_length() {
+        let message = crate::MessageBuilder::new()
+            .add_header_str("content-length", "1")
+            .unwrap()
+            .set_body_str("crème")
+            .unwrap()
+            .with_content_length()
+            .build()
+            .unwrap();
+
+        assert_eq!(message.header(), "content-length: 6");
+
+        let message = crate::MessageBuilder::new()
+            .with_content_length()
+            .build()
+            .unwrap();
+
+        assert_eq!(message.header_value("Content-Length"), Some("0"));
+    }
+
+    #[test]
+    fn test_from_bytes_with_remainder() {
+        use crate::{DefaultMessage, ParseError};
+
+        let first = crate::MessageBuilder::new()
+            .set_body_str("one")
+            .unwrap()
+            .build()
+            .unwrap();
+        let second = crate::MessageBuilder::new()
+            .add_header_str("Id", "2")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let mut stream = std::vec::Vec::new();
+        stream.extend_from_slice(&first.to_bytes::<64>().unwrap());
+        stream.extend_from_slice(&second.to_bytes::<64>().unwrap());
+        stream.extend_from_slice(&[1, 2, 1]);
+
+        let (message, rest) = DefaultMessage::from_bytes_with_remainder(&stream).unwrap();
+        assert_eq!(message, first);
+        let (message, rest) = DefaultMessage::from_bytes_with_remainder(rest).unwrap();
+        assert_eq!(message, second);
+        assert_eq!(rest, &[1, 2, 1]);
+
+        assert_eq!(
+            DefaultMessage::from_bytes_with_remainder(rest),
+            Err(ParseError::TooShort)
+        );
+        assert_eq!(
+            DefaultMessage::from_bytes_with_remainder(&[0, 1, 2]),
+            Err(ParseError::BadStartMarker)
+        );
+    }
+
+    #[test]
+    fn test_long_header_len_is_little_endian() {
+        let value = "v".repeat(120);
+        let mut builder = crate::MessageBuilder::new();
+        for key in ["A", "B", "C"] {
+            builder = builder.add_header_str(key, &value).unwrap();
+        }
+        let message = builder.build().unwrap();
+        assert_eq!(message.header.len(), 373);
+
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+
+        assert_eq!(crate::frame::u16_to_u8s(0x0175), [0x75, 0x01]);
+        assert_eq!(&bytes[4..6], &[0x75, 0x01]);
+        assert_eq!(crate::DefaultMessage::from_bytes(&bytes), Ok(message));
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut builder = crate::MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .set_body_str("one")
+            .unwrap()
+            .with_content_length();
+
+        builder.clear();
+
+        let message = builder.add_header_str("Id", "2").unwrap().build().unwrap();
+
+        assert_eq!(message.header(), "Id: 2\r\nContent-Length: 0");
+        assert!(!message.has_body());
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        use crate::EncodeError;
+
+        let message = crate::MessageBuilder::new()
+            .add_header_str("Id", "7")
+            .unwrap()
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+        let len = message.encoded_len();
+        assert_eq!(len, 23);
+
+        assert_eq!(message.to_bytes::<4>(), Err(EncodeError::PreambleOverflow));
+        assert_eq!(message.to_bytes::<8>(), Err(EncodeError::HeaderOverflow));
+        assert_eq!(message.to_bytes::<14>(), Err(EncodeError::BodyOverflow));
+        assert_eq!(message.to_bytes::<22>(), Err(EncodeError::TrailerOverflow));
+        assert!(message.to_bytes::<23>().is_ok());
+    }
+
+    #[test]
+    fn test_max_size_frame() {
+        use std::string::String as StdString;
+
+        let mut header = StdString::from("Filler: ");
+        header.push_str(&"h".repeat(crate::MAX_BODY_SIZE - header.len()));
+
+        let message = crate::DefaultMessage {
+            frame_type: crate::FrameType::Request,
+            header: String::from_str(&header).unwrap(),
+            body: Some(heapless::Vec::from_slice(&[b'b'; crate::MAX_BODY_SIZE]).unwrap()),
+            trailers: String::new(),
+            seq: None,
+        };
+
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        assert!(bytes.len() <= crate::MAX_BODY_SIZE * 2 + crate::FRAME_OVERHEAD);
+        assert_eq!(crate::DefaultMessage::from_bytes(&bytes), Ok(message));
+    }
+
+    #[test]
+    fn test_header_at_capacity() {
+        use crate::{
+            BuildError, DefaultMessage, FrameDecoder, Message, MessageBuilder, ParseError,
+            ReadError, MAX_BODY_SIZE,
+        };
+
+        let mut header: String<MAX_BODY_SIZE> = String::from_str("Filler: ").unwrap();
+        while header.push('h').is_ok() {}
+        assert_eq!(header.len(), MAX_BODY_SIZE);
+
+        let message = MessageBuilder::new()
+            .set_raw_header(header.clone())
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        assert_eq!(&bytes[4..6], &(MAX_BODY_SIZE as u16).to_le_bytes());
+        assert_eq!(
+            bytes.len(),
+            Message::min_frame_size_for_header(MAX_BODY_SIZE, false).unwrap()
+        );
+        assert_eq!(DefaultMessage::from_bytes(&bytes), Ok(message.clone()));
+        assert_eq!(
+            DefaultMessage::read_from(&mut &bytes[..]),
+            Ok(message.clone())
+        );
+
+        let mut decoder: FrameDecoder = FrameDecoder::new();
+        decoder.push(&bytes).unwrap();
+        assert_eq!(decoder.poll(), Some(message));
+
+        // Anything more is refused cleanly on both sides
+        assert_eq!(
+            MessageBuilder::new()
+                .set_raw_header(header.clone())
+                .try_add_header(String::from_str("A").unwrap(), String::new())
+                .err(),
+            Some(BuildError::HeaderTooLarge { index: 0 })
+        );
+
+        let mut long: String<{ MAX_BODY_SIZE + 1 }> = String::from_str(&header).unwrap();
+        long.push('h').unwrap();
+
+        let message = MessageBuilder::<{ MAX_BODY_SIZE + 1 }, 8>::default()
+            .set_raw_header(long)
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        assert_eq!(
+            DefaultMessage::from_bytes(&bytes),
+            Err(ParseError::HeaderLengthOverflow)
+        );
+        assert_eq!(
+            DefaultMessage::read_from(&mut &bytes[..]),
+            Err(ReadError::Parse(ParseError::HeaderLengthOverflow))
+        );
+    }
+
+    #[test]
+    fn test_header_iter() {
+        let message = crate::DefaultMessage {
+            frame_type: crate::FrameType::Request,
+            header: String::from_str("A: 1\r\nmalformed\r\nB: x: y").unwrap(),
+            body: Some(heapless::Vec::new()),
+            trailers: String::new(),
+            seq: None,
+        };
+
+        assert!(message.header_iter().eq([("A", "1"), ("B", "x: y")]));
+
+        let empty = crate::MessageBuilder::new().build().unwrap();
+        assert_eq!(empty.header_iter().count(), 0);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_alloc() {
+        let message = crate::MessageBuilder::<16, 8>::default()
+            .add_header_str("Id", "\x02")
+            .unwrap()
+            .set_body_bytes(heapless::Vec::from_slice(&[0x0, 0x1B, b'a']).unwrap())
+            .build()
+            .unwrap();
+
+        let bytes = message.to_vec();
+        assert_eq!(bytes.len(), message.encoded_len());
+
+        let borrowed = crate::Message::from_bytes_ref(&bytes).unwrap();
+        assert_eq!(borrowed.header_string().unwrap(), "Id: \x02");
+        assert_eq!(borrowed.body_vec().unwrap(), [0x0, 0x1B, b'a']);
+    }
+
+    #[test]
+    fn test_header_eq_ct() {
+        let message = crate::MessageBuilder::new()
+            .add_header_str("Token", "s3cret")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert!(message.header_eq_ct("token", b"s3cret"));
+        assert!(!message.header_eq_ct("Token", b"s3creT"));
+        assert!(!message.header_eq_ct("Token", b"s3cre"));
+        assert!(!message.header_eq_ct("Token", b"s3crets"));
+        assert!(!message.header_eq_ct("Other", b"s3cret"));
+    }
+
+    #[test]
+    fn test_no_body() {
+        use crate::{DefaultMessage, FrameDecoder, MessageBuilder};
+
+        let ping = MessageBuilder::new().build().unwrap();
+        let empty = MessageBuilder::new()
+            .set_body_str("")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert!(!ping.has_body());
+        assert!(empty.has_body());
+        assert_ne!(ping, empty);
+
+        let ping_bytes: crate::FrameBuffer = ping.to_bytes().unwrap();
+        let empty_bytes: crate::FrameBuffer = empty.to_bytes().unwrap();
+
+        assert_eq!(ping_bytes.len() + 3, empty_bytes.len());
+        assert_eq!(&ping_bytes[6..7], &[0x0]);
+        assert_eq!(&empty_bytes[6..10], &[0x0, 0x3, 0x2, 0x0]);
+
+        assert_eq!(DefaultMessage::from_bytes(&ping_bytes), Ok(ping.clone()));
+        assert_eq!(DefaultMessage::from_bytes(&empty_bytes), Ok(empty.clone()));
+        assert_eq!(
+            DefaultMessage::read_from(&mut &ping_bytes[..]),
+            Ok(ping.clone())
+        );
+        assert_eq!(
+            DefaultMessage::read_from(&mut &empty_bytes[..]),
+            Ok(empty.clone())
+        );
+        assert!(!crate::Message::from_bytes_ref(&ping_bytes)
+            .unwrap()
+            .has_body());
+
+        let mut decoder: FrameDecoder = FrameDecoder::new();
+        decoder.push(&ping_bytes).unwrap();
+        decoder.push(&empty_bytes).unwrap();
+        assert_eq!(decoder.poll(), Some(ping));
+        assert_eq!(decoder.poll(), Some(empty));
+    }
+
+    #[test]
+    fn test_from_bytes_scanning() {
+        use crate::{DefaultMessage, Message, MessageBuilder, ParseError};
+
+        let one = MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .build()
+            .unwrap();
+        let two = MessageBuilder::new()
+            .add_header_str("Id", "2")
+            .unwrap()
+            .set_body_str("two")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let one_bytes: crate::FrameBuffer = one.to_bytes().unwrap();
+        let two_bytes: crate::FrameBuffer = two.to_bytes().unwrap();
+
+        // Noise, a frame, a corrupt frame, then another frame
+        let mut stream = std::vec::Vec::from([0xff, 0x4, 0x1]);
+        stream.extend_from_slice(&one_bytes);
+        stream.extend_from_slice(&[0x1, 0x2, 0x2, 0x0, 0x0, 0x0, 0x7, 0x7, 0x4, 0x0]);
+        stream.extend_from_slice(&two_bytes);
+        stream.push(0x1);
+
+        assert_eq!(Message::find_frame_start(&stream), Some(3));
+
+        let (first, rest) = DefaultMessage::from_bytes_scanning(&stream).unwrap();
+        let (second, rest) = DefaultMessage::from_bytes_scanning(rest).unwrap();
+        assert_eq!(first, one);
+        assert_eq!(second, two);
+        assert_eq!(rest, &[0x1]);
+
+        assert_eq!(
+            DefaultMessage::from_bytes_scanning(rest),
+            Err(ParseError::BadStartMarker)
+        );
+        assert_eq!(Message::find_frame_start(&[0x2, 0x1]), None);
+    }
+
+    #[test]
+    fn test_is_valid_frame() {
+        use crate::{Message, MessageBuilder};
+
+        let message = MessageBuilder::new()
+            .set_body_str("ok")
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+
+        assert!(Message::is_valid_frame(&bytes));
+        assert!(!Message::is_valid_frame(&bytes[..bytes.len() - 1]));
+        assert!(!Message::is_valid_frame(&bytes[1..]));
+        assert!(!Message::is_valid_frame(&[1, 2, 2, 0, 0xff, 0, 0, 4]));
+
+        // The checksum is left to a full parse
+        bytes[8] = b'K';
+        assert!(Message::is_valid_frame(&bytes));
+        assert!(Message::from_bytes_ref(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_byte_accessors() {
+        let message = crate::MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .set_body_bytes(heapless::Vec::from_slice(&[0xde, 0xad]).unwrap())
+            .build()
+            .unwrap();
+
+        assert_eq!(message.header_bytes(), b"Id: 1");
+        assert_eq!(message.body_bytes(), &[0xde, 0xad]);
+    }
+
+    #[test]
+    fn test_raw_header() {
+        use crate::{BuildError, Message, MessageBuilder};
+
+        let raw = String::from_str("A: 1\r\nB: 2").unwrap();
+
+        let message = MessageBuilder::new()
+            .set_raw_header(raw.clone())
+            .set_body_str("body")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(message.header(), "A: 1\r\nB: 2");
+
+        let message = MessageBuilder::new()
+            .set_raw_header(raw)
+            .add_header_str("C", "3")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(message.header(), "A: 1\r\nB: 2\r\nC: 3");
+
+        assert_eq!(
+            MessageBuilder::<8, 8>::default()
+                .set_raw_header(String::from_str("A: 1").unwrap())
+                .add_header_str("B", "2")
+                .unwrap()
+                .build(),
+            Err::<Message<8, 8>, _>(BuildError::HeaderTooLarge { index: 0 })
+        );
+    }
+
+    #[test]
+    fn test_body_marker_is_anchored() {
+        use crate::{Message, MessageBuilder, ParseError};
+
+        let message = MessageBuilder::new()
+            .set_body_str("body")
+            .unwrap()
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        assert_eq!(&bytes[6..9], &[0x0, 0x3, 0x2]);
+
+        let mut moved = std::vec::Vec::from(&bytes[..8]);
+        moved.push(b'x');
+        moved.extend_from_slice(&bytes[8..]);
+        assert_eq!(
+            Message::from_bytes_ref(&moved),
+            Err(ParseError::MissingBodyMarker)
+        );
+
+        let mut missing = bytes.clone();
+        missing[8] = b'x';
+        assert_eq!(
+            Message::from_bytes_ref(&missing),
+            Err(ParseError::MissingBodyMarker)
+        );
+    }
+
+    #[test]
+    fn test_try_from() {
+        use crate::{DefaultMessage, EncodeError, Message, MessageBuilder, ParseError};
+
+        let message = MessageBuilder::new()
+            .set_body_str("hi")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let bytes: crate::FrameBuffer = message.clone().try_into().unwrap();
+        assert_eq!(DefaultMessage::try_from(&bytes[..]), Ok(message.clone()));
+
+        let small: Result<heapless::Vec<u8, 4>, _> = message.try_into();
+        assert_eq!(small, Err(EncodeError::PreambleOverflow));
+        assert_eq!(
+            Message::<8, 8>::try_from(&[][..]),
+            Err(ParseError::TooShort)
+        );
+    }
+
+    #[test]
+    fn test_ping() {
+        use crate::{DefaultMessage, FrameType, Message, MessageBuilder};
+
+        let ping = DefaultMessage::ping();
+        assert!(ping.is_ping());
+        assert_eq!(ping.frame_type(), FrameType::Ping);
+        assert_eq!(
+            MessageBuilder::new().frame_type(FrameType::Ping).build(),
+            Ok(ping.clone())
+        );
+        assert!(!MessageBuilder::new().build().unwrap().is_ping());
+
+        let bytes: crate::FrameBuffer = ping.to_bytes().unwrap();
+        assert_eq!(
+            &bytes[..],
+            &[0x1, 0x2, 0x2, 0x3, 0x0, 0x0, 0x0, 0xff, 0xff, 0x4]
+        );
+        assert_eq!(DefaultMessage::from_bytes(&bytes), Ok(ping));
+        assert!(Message::<0, 0>::from_bytes(&bytes).unwrap().is_ping());
+
+        let empty = MessageBuilder::new()
+            .set_body_str("")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(!empty.is_ping());
+    }
+
+    #[test]
+    fn test_try_add_header() {
+        use crate::{BuildError, MessageBuilder};
+
+        let key = |k: &str| String::from_str(k).unwrap();
+        let value = |v: &str| String::from_str(v).unwrap();
+
+        let builder = MessageBuilder::<16, 8>::default()
+            .try_add_header(key("A"), value("1"))
+            .unwrap()
+            .try_add_header(key("B"), value("22"))
+            .unwrap();
+        assert_eq!(builder.header_count(), 2);
+        assert_eq!(builder.headers_len(), "A: 1\r\nB: 22".len());
+
+        let builder = builder.try_add_header(key("C"), value("")).unwrap();
+        assert_eq!(builder.headers_len(), 16);
+        assert_eq!(builder.build().unwrap().header(), "A: 1\r\nB: 22\r\nC: ");
+
+        let builder = MessageBuilder::<16, 8>::default()
+            .try_add_header(key("A"), value("1"))
+            .unwrap();
+        assert_eq!(
+            builder.try_add_header(key("a"), value("2")).err(),
+            Some(BuildError::DuplicateHeader)
+        );
+
+        let builder = MessageBuilder::<16, 8>::default()
+            .try_add_header(key("A"), value("1"))
+            .unwrap();
+        assert_eq!(
+            builder.try_add_header(key("B"), value("12345678")).err(),
+            Some(BuildError::HeaderTooLarge { index: 1 })
+        );
+
+        let mut builder = MessageBuilder::new();
+        for i in 0..16 {
+            let k = std::format!("K{}", i);
+            builder = builder.try_add_header(key(&k), value("v")).unwrap();
+        }
+        assert_eq!(
+            builder.try_add_header(key("X"), value("v")).err(),
+            Some(BuildError::TooManyHeaders)
+        );
+    }
+
+    #[test]
+    fn test_with_body_and_header_value() {
+        use crate::{BuildError, Message, MessageBuilder, MAX_HEADERS};
+
+        let message = MessageBuilder::<32, 8>::default()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .add_header_str("Type", "text")
+            .unwrap()
+            .set_body_str("old")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let message = message.with_body(String::from_str("new").unwrap());
+        assert_eq!(message.body(), b"new");
+
+        let message = message.with_header_value("id", "22").unwrap();
+        assert_eq!(message.header(), "Id: 22\r\nType: text");
+
+        let message = message.with_header_value("Extra", "x").unwrap();
+        assert_eq!(message.header(), "Id: 22\r\nType: text\r\nExtra: x");
+        assert_eq!(message.header_value("extra"), Some("x"));
+
+        assert_eq!(
+            message.clone().with_header_value("Id", "1\r\nEvil: 1"),
+            Err(BuildError::InvalidHeaderValue)
+        );
+        assert_eq!(
+            message.with_header_value("Longer", "header that won't fit"),
+            Err::<Message<32, 8>, _>(BuildError::HeaderTooLarge { index: 3 })
+        );
+
+        let mut full = MessageBuilder::new();
+        for i in 0..MAX_HEADERS {
+            let key: String<32> = String::try_from(i as u32).unwrap();
+            full = full.try_add_header(key, String::new()).unwrap();
+        }
+        let full = full.build().unwrap();
+        assert_eq!(
+            full.clone().with_header_value("Extra", "x"),
+            Err(BuildError::TooManyHeaders)
+        );
+        // Replacing a value adds no line
+        let replaced = full.with_header_value("0", "x").unwrap();
+        assert_eq!(replaced.header_value("0"), Some("x"));
+        assert_eq!(replaced.header_count(), MAX_HEADERS);
+    }
+
+    #[test]
+    fn test_max_length_header() {
+        let key = "k".repeat(32);
+        let value = "v".repeat(128);
+
+        let message = crate::MessageBuilder::new()
+            .add_header_str(&key, &value)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(message.header().len(), 32 + 2 + 128);
+        assert_eq!(message.header_value(&key), Some(value.as_str()));
+    }
+
+    #[test]
+    fn test_method_and_status() {
+        use crate::MessageBuilder;
+
+        let request = MessageBuilder::new()
+            .add_header_str("Id", "7")
+            .unwrap()
+            .method("GET")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(request.header(), "Method: GET\r\nId: 7");
+        assert_eq!(request.method(), Some("GET"));
+        assert_eq!(request.status(), None);
+
+        let response = MessageBuilder::new()
+            .method("GET")
+            .unwrap()
+            .status(404)
+            .set_raw_header(String::from_str("Raw: 1").unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(response.header(), "Status: 404\r\nRaw: 1");
+        assert_eq!(response.status(), Some(404));
+        assert_eq!(response.method(), None);
+
+        let plain = MessageBuilder::new()
+            .add_header_str("Id", "7")
+            .unwrap()
+            .add_header_str("Method", "GET")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(plain.method(), None);
+
+        let builder = MessageBuilder::new().status(200);
+        assert_eq!(builder.headers_len(), "Status: 200".len());
+        assert_eq!(
+            MessageBuilder::new().method(&"x".repeat(33)).err(),
+            Some(crate::BuildError::HeaderTooLarge { index: 0 })
+        );
+    }
+
+    #[test]
+    fn test_hash_dedup() {
+        use crate::MessageBuilder;
+
+        let message = |id| {
+            MessageBuilder::new()
+                .add_header_str("Id", id)
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+
+        let mut seen = std::collections::HashSet::new();
+        assert!(seen.insert(message("1")));
+        assert!(seen.insert(message("2")));
+        assert!(!seen.insert(message("1")));
+    }
+
+    #[test]
+    fn test_new() {
+        use crate::{BuildError, DefaultMessage, Message, MessageBuilder};
+
+        let message = DefaultMessage::new("Content-Type: text/plain", "hello").unwrap();
+        assert_eq!(
+            message,
+            MessageBuilder::new()
+                .add_header_str("Content-Type", "text/plain")
+                .unwrap()
+                .set_body_str("hello")
+                .unwrap()
+                .build()
+                .unwrap()
+        );
+        assert_eq!(
+            DefaultMessage::new("", "").unwrap().header_iter().count(),
+            0
+        );
+
+        assert_eq!(
+            DefaultMessage::new("no separator", ""),
+            Err(BuildError::InvalidHeader)
+        );
+        assert_eq!(
+            DefaultMessage::new("A: 1\r\nB: 2", ""),
+            Err(BuildError::InvalidHeader)
+        );
+        assert_eq!(
+            DefaultMessage::new(": x", ""),
+            Err(BuildError::EmptyHeaderKey)
+        );
+        assert_eq!(
+            DefaultMessage::new("a\u{1}b: x", ""),
+            Err(BuildError::InvalidHeaderKey)
+        );
+        assert_eq!(
+            Message::<4, 4>::new("A: 12", ""),
+            Err(BuildError::HeaderTooLarge { index: 0 })
+        );
+        assert_eq!(
+            Message::<4, 4>::new("A: 1", "hello"),
+            Err(BuildError::BodyTooLarge)
+        );
+    }
+
+    #[test]
+    fn test_parse_parts() {
+        use crate::{Message, ParseError};
+
+        let message = crate::DefaultMessage::new("Marker: \u{2}", "body").unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+
+        let (header_len, header, body) = Message::parse_parts(&bytes).unwrap();
+        assert_eq!(header_len, 10);
+        assert_eq!(header, b"Marker: \x1b\x22");
+        assert_eq!(body, b"body");
+
+        assert_eq!(
+            Message::parse_parts(&bytes[1..]),
+            Err(ParseError::BadStartMarker)
+        );
+    }
+
+    #[test]
+    fn test_truncated_frames() {
+        use crate::{DefaultMessage, ParseError};
+
+        assert_eq!(
+            DefaultMessage::from_bytes(&[1, 2, 2, 0, 0, 0, 0, 3, 2, 4]),
+            Err(ParseError::MissingChecksum)
+        );
+        assert_eq!(
+            DefaultMessage::from_bytes(&[1, 2, 2, 0, 0, 0, 0, 3, 2, 0, 4]),
+            Err(ParseError::MissingChecksum)
+        );
+
+        let message = DefaultMessage::new("Id: 1", "body").unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+
+        // Every truncation, with and without a new end marker, is an error
+        for len in 0..bytes.len() - 1 {
+            let mut truncated = std::vec::Vec::from(&bytes[..len]);
+            assert!(DefaultMessage::from_bytes(&truncated).is_err());
+            truncated.push(0x4);
+            assert!(DefaultMessage::from_bytes(&truncated).is_err());
+        }
+    }
+
+    #[test]
+    fn test_extend_headers() {
+        use crate::{BuildError, MessageBuilder};
+
+        let pair = |k: &str, v: &str| (String::from_str(k).unwrap(), String::from_str(v).unwrap());
+        let headers: heapless::Vec<_, 2> = [pair("A", "1"), pair("B", "2")].into_iter().collect();
+
+        let message = MessageBuilder::new()
+            .add_header_str("First", "0")
+            .unwrap()
+            .extend_headers(headers)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(message.header(), "First: 0\r\nA: 1\r\nB: 2");
+
+        assert_eq!(
+            MessageBuilder::new()
+                .extend_headers([pair("A", "1"), pair("a", "2")])
+                .err(),
+            Some(BuildError::DuplicateHeader)
+        );
+    }
+
+    #[test]
+    fn test_lf_separator() {
+        use crate::{BuildError, DefaultMessage, LineSeparator, MessageBuilder};
+
+        let message = MessageBuilder::new()
+            .with_separator(LineSeparator::Lf)
+            .add_header_str("Id", "1")
+            .unwrap()
+            .add_header_str("Type", "text")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(message.header(), "Id: 1\nType: text");
+
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        let parsed = DefaultMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.header_value("type"), Some("text"));
+        assert_eq!(parsed.header_iter().count(), 2);
+        assert_eq!(parsed.headers().unwrap().len(), 2);
+
+        let parsed = parsed.with_header_value("Extra", "x").unwrap();
+        assert_eq!(parsed.header(), "Id: 1\nType: text\nExtra: x");
+        assert_eq!(
+            parsed.with_header_value("Id", "1\nEvil: 1"),
+            Err(BuildError::InvalidHeaderValue)
+        );
+
+        assert_eq!(
+            DefaultMessage::new("Id: 1\nEvil: 1", "").err(),
+            Some(BuildError::InvalidHeader)
+        );
+    }
+
+    #[test]
+    fn test_encode_into() {
+        use crate::{EncodeError, MessageBuilder};
+
+        let message = MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let mut buf = [0xaa; 64];
+        let len = message.encode_into(&mut buf).unwrap();
+        assert_eq!(len, message.encoded_len());
+        assert_eq!(buf[len], 0xaa);
+
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        assert_eq!(&buf[..len], &bytes[..]);
+
+        assert_eq!(
+            message.encode_into(&mut buf[..len - 1]),
+            Err(EncodeError::TrailerOverflow)
+        );
+        assert_eq!(
+            message.encode_into(&mut buf[..4]),
+            Err(EncodeError::PreambleOverflow)
+        );
+    }
+
+    #[test]
+    fn test_header_count_and_body_len() {
+        use crate::{DefaultMessage, MessageBuilder};
+
+        assert_eq!(DefaultMessage::ping().header_count(), 0);
+        assert_eq!(DefaultMessage::ping().body_len(), 0);
+
+        let message = MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .add_header_str("Type", "text")
+            .unwrap()
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        let parsed = DefaultMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.header_count(), 2);
+        assert_eq!(parsed.body_len(), 5);
+    }
+
+    #[test]
+    fn test_invalid_header_key() {
+        use crate::{BuildError, CapacityError, DefaultMessage, Message, MessageBuilder};
+
+        let key = |k: &str| heapless::String::try_from(k).unwrap();
+        let value = |v: &str| heapless::String::try_from(v).unwrap();
+
+        for bad in ["Id:", "I\nd", "I\x01d", "Café"] {
+            assert_eq!(
+                MessageBuilder::new()
+                    .try_add_header(key(bad), value("1"))
+                    .err(),
+                Some(BuildError::InvalidHeaderKey)
+            );
+            assert!(MessageBuilder::new()
+                .add_header(key(bad), value("1"))
+                .is_none());
+            assert_eq!(
+                MessageBuilder::new().add_header_str(bad, "1").err(),
+                Some(CapacityError)
+            );
+            assert_eq!(
+                DefaultMessage::ping().with_header_value(bad, "1"),
+                Err(BuildError::InvalidHeaderKey)
+            );
+
+            // The constructors that take a whole header line follow the same
+            // rules, unless the key already breaks the line
+            if bad.contains('\n') {
+                continue;
+            }
+            let line: heapless::String<16> = [bad, "1"].join(": ").as_str().try_into().unwrap();
+            assert_eq!(
+                DefaultMessage::new(&line, ""),
+                Err(BuildError::InvalidHeaderKey)
+            );
+            assert_eq!(
+                Message::<16, 0>::from_parts(line, None),
+                Err(BuildError::InvalidHeaderKey)
+            );
+        }
+
+        assert_eq!(
+            MessageBuilder::new()
+                .try_add_header(key("Id"), value("1\r\nEvil: 1"))
+                .err(),
+            Some(BuildError::InvalidHeaderValue)
+        );
+        assert!(MessageBuilder::new()
+            .add_header_str("X-Id", "any \x01 value: ok")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_build_sorted() {
+        use crate::MessageBuilder;
+
+        let headers = [("Type", "text"), ("id", "1"), ("Accept", "*/*")];
+
+        let mut forward = MessageBuilder::new();
+        for (k, v) in headers {
+            forward = forward.add_header_str(k, v).unwrap();
+        }
+        let mut backward = MessageBuilder::new();
+        for (k, v) in headers.iter().rev() {
+            backward = backward.add_header_str(k, v).unwrap();
+        }
+
+        let forward = forward.set_body_str("hi").unwrap().build_sorted().unwrap();
+        let backward = backward.set_body_str("hi").unwrap().build_sorted().unwrap();
+        assert_eq!(forward.header(), "Accept: */*\r\nid: 1\r\nType: text");
+
+        let forward: crate::FrameBuffer = forward.to_bytes().unwrap();
+        let backward: crate::FrameBuffer = backward.to_bytes().unwrap();
+        assert_eq!(forward, backward);
+    }
+
+    #[test]
+    fn test_too_many_headers() {
+        use crate::{FrameType, Message, ParseError, ReadError, MAX_HEADERS};
+
+        let frame = |lines: usize| {
+            let mut raw: heapless::String<256> = heapless::String::new();
+            for i in 0..lines {
+                if i > 0 {
+                    raw.push_str("\r\n").unwrap();
+                }
+                raw.push_str("a: 1").unwrap();
+            }
+            // Built directly, since the builder refuses too many lines
+            Message::<256, 8> {
+                frame_type: FrameType::Request,
+                header: raw,
+                body: None,
+                trailers: heapless::String::new(),
+                seq: None,
+            }
+            .to_bytes::<512>()
+            .unwrap()
+        };
+
+        let ok = frame(MAX_HEADERS);
+        assert!(Message::<256, 8>::from_bytes(&ok).is_ok());
+
+        let hostile = frame(MAX_HEADERS + 1);
+        assert_eq!(
+            Message::<256, 8>::from_bytes(&hostile),
+            Err(ParseError::TooManyHeaders)
+        );
+        assert_eq!(
+            Message::<256, 8>::read_from(&mut &hostile[..]),
+            Err(ReadError::Parse(ParseError::TooManyHeaders))
+        );
+    }
+
+    #[test]
+    fn test_push_body_str() {
+        use crate::{CapacityError, MessageBuilder};
+
+        let mut builder = MessageBuilder::<16, 8>::default();
+        builder.push_body_str("{\"a\":").unwrap();
+        builder.push_body_str("1}").unwrap();
+        assert_eq!(builder.push_body_str("too long"), Err(CapacityError));
+
+        let message = builder.build().unwrap();
+        assert_eq!(message.body(), b"{\"a\":1}");
+
+        let mut builder = MessageBuilder::<16, 8>::default();
+        builder.push_body_str("").unwrap();
+        assert!(builder.build().unwrap().has_body());
+
+        let mut builder = MessageBuilder::<16, 8>::default();
+        assert_eq!(builder.push_body_str("too long!"), Err(CapacityError));
+        assert!(!builder.build().unwrap().has_body());
+    }
+
+    #[test]
+    fn test_eq_ignoring_header_order() {
+        use crate::{DefaultMessage, MessageBuilder};
+
+        let build = |headers: &[(&str, &str)], body: &str| {
+            let mut builder = MessageBuilder::new();
+            for (k, v) in headers {
+                builder = builder.add_header_str(k, v).unwrap();
+            }
+            builder.set_body_str(body).unwrap().build().unwrap()
+        };
+
+        let a = build(&[("Id", "1"), ("Type", "text")], "hi");
+        let b = build(&[("Type", "text"), ("Id", "1")], "hi");
+        assert_ne!(a, b);
+        assert!(a.eq_ignoring_header_order(&b));
+
+        assert!(!a.eq_ignoring_header_order(&build(&[("Id", "1")], "hi")));
+        assert!(!a.eq_ignoring_header_order(&build(&[("Type", "text"), ("Id", "2")], "hi")));
+        assert!(!a.eq_ignoring_header_order(&build(&[("Type", "text"), ("Id", "1")], "ho")));
+
+        // Repeated keys are kept rather than collapsed to the last value
+        let repeated =
+            DefaultMessage::from_parts(String::from_str("A: 1\r\nA: 2").unwrap(), None).unwrap();
+        let single = DefaultMessage::from_parts(String::from_str("A: 2").unwrap(), None).unwrap();
+        let swapped =
+            DefaultMessage::from_parts(String::from_str("A: 2\r\nA: 1").unwrap(), None).unwrap();
+        assert!(!repeated.eq_ignoring_header_order(&single));
+        assert!(!single.eq_ignoring_header_order(&repeated));
+        assert!(repeated.eq_ignoring_header_order(&swapped));
+    }
+
+    #[test]
+    fn test_into_and_from_parts() {
+        use crate::{BuildError, DefaultMessage, MessageBuilder};
+
+        let message = MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .set_body_str("hi")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let (header, body) = message.clone().into_parts();
+        assert_eq!(header, "Id: 1");
+        assert_eq!(body.as_deref(), Some(&b"hi"[..]));
+        assert_eq!(DefaultMessage::from_parts(header, body), Ok(message));
+
+        assert_eq!(
+            DefaultMessage::from_parts(heapless::String::new(), None)
+                .unwrap()
+                .header_count(),
+            0
+        );
+        assert_eq!(
+            DefaultMessage::from_parts(heapless::String::try_from("Id 1").unwrap(), None),
+            Err(BuildError::InvalidHeader)
+        );
+        assert_eq!(
+            DefaultMessage::from_parts(heapless::String::try_from("I\x01d: 1").unwrap(), None),
+            Err(BuildError::InvalidHeaderKey)
+        );
+    }
+
+    #[test]
+    fn test_frame_type() {
+        use crate::{DefaultMessage, FrameType, Message, MessageBuilder, ParseError, ReadError};
+
+        for frame_type in [FrameType::Request, FrameType::Response, FrameType::Event] {
+            let message = MessageBuilder::new()
+                .frame_type(frame_type)
+                .add_header_str("Id", "1")
+                .unwrap()
+                .build()
+                .unwrap();
+            let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+            assert_eq!(bytes[3], frame_type as u8);
+
+            let parsed = DefaultMessage::from_bytes(&bytes).unwrap();
+            assert_eq!(parsed.frame_type(), frame_type);
+            assert_eq!(DefaultMessage::read_from(&mut &bytes[..]), Ok(message));
+            assert_eq!(
+                Message::from_bytes_ref(&bytes).unwrap().frame_type(),
+                frame_type
+            );
+        }
+
+        let mut bytes: crate::FrameBuffer = DefaultMessage::ping().to_bytes().unwrap();
+        bytes[3] = 9;
+        assert_eq!(
+            DefaultMessage::from_bytes(&bytes),
+            Err(ParseError::UnknownFrameType { found: 9 })
+        );
+        assert_eq!(
+            DefaultMessage::read_from(&mut &bytes[..]),
+            Err(ReadError::Parse(ParseError::UnknownFrameType { found: 9 }))
+        );
+    }
+
+    #[test]
+    fn test_header_injection() {
+        use crate::{BuildError, LineSeparator, MessageBuilder};
+
+        for value in ["1\r\nAdmin: yes", "1\nAdmin: yes", "1\rAdmin: yes"] {
+            assert!(MessageBuilder::new().add_header_str("Id", value).is_err());
+            assert!(MessageBuilder::new()
+                .with_separator(LineSeparator::Lf)
+                .append_header(
+                    heapless::String::try_from("Id").unwrap(),
+                    heapless::String::try_from(value).unwrap()
+                )
+                .is_none());
+            assert_eq!(
+                MessageBuilder::new()
+                    .extend_headers([(
+                        heapless::String::try_from("Id").unwrap(),
+                        heapless::String::try_from(value).unwrap()
+                    )])
+                    .err(),
+                Some(BuildError::InvalidHeaderValue)
+            );
+            assert_eq!(
+                MessageBuilder::new().method(value).err(),
+                Some(BuildError::InvalidHeaderValue)
+            );
+        }
+    }
+
+    #[test]
+    fn test_matches_route() {
+        use crate::{MessageBuilder, PATH_HEADER};
+
+        let request = |method: &str, path: &str| {
+            MessageBuilder::new()
+                .method(method)
+                .unwrap()
+                .add_header_str(PATH_HEADER, path)
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+
+        let message = request("GET", "/users/7");
+        assert!(message.matches_route("GET", "/users/7"));
+        assert!(message.matches_route("GET", "/users"));
+        assert!(message.matches_route("GET", "/users/"));
+        assert!(message.matches_route("GET", "/"));
+        assert!(!message.matches_route("POST", "/users"));
+        assert!(!message.matches_route("GET", "/use"));
+        assert!(!request("GET", "/usersettings").matches_route("GET", "/users"));
+
+        let no_method = MessageBuilder::new()
+            .add_header_str(PATH_HEADER, "/users")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(!no_method.matches_route("GET", "/users"));
+        assert!(!MessageBuilder::new()
+            .method("GET")
+            .unwrap()
+            .build()
+            .unwrap()
+            .matches_route("GET", "/"));
+    }
+
+    #[test]
+    fn test_peek() {
+        use crate::{DefaultMessage, Message, MessageBuilder, ParseError};
+
+        let message = MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        assert_eq!(Message::peek_header(&bytes), Ok(&b"Id: 1"[..]));
+        assert_eq!(Message::peek_body(&bytes), Ok(&b"hello"[..]));
+
+        let mut corrupt = bytes.clone();
+        let last = corrupt.len() - 2;
+        corrupt[last] ^= 0x40;
+        assert_eq!(Message::peek_body(&corrupt), Ok(&b"hello"[..]));
+        assert!(DefaultMessage::from_bytes(&corrupt).is_err());
+
+        let ping: crate::FrameBuffer = DefaultMessage::ping().to_bytes().unwrap();
+        assert_eq!(Message::peek_header(&ping), Ok(&b""[..]));
+        assert_eq!(Message::peek_body(&ping), Ok(&b""[..]));
+
+        assert_eq!(
+            Message::peek_body(&bytes[1..]),
+            Err(ParseError::BadStartMarker)
+        );
+    }
+
+    #[test]
+    fn test_long_header() {
+        use crate::{Message, MessageBuilder, LONG_HEADER_VERSION, PROTOCOL_VERSION};
+
+        const H: usize = 70_000;
+        let mut raw: heapless::String<H> = heapless::String::new();
+        raw.push_str("Filler: ").unwrap();
+        while raw.len() < usize::from(u16::MAX) + 1 {
+            raw.push('x').unwrap();
+        }
+
+        let message = MessageBuilder::<H, 8>::default()
+            .set_raw_header(raw)
+            .set_body_str("hi")
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut frame = std::vec![0u8; message.encoded_len()];
+        assert_eq!(message.encode_into(&mut frame), Ok(frame.len()));
+
+        assert_eq!(frame[2], LONG_HEADER_VERSION);
+        assert_eq!(&frame[4..8], &0x10000u32.to_le_bytes());
+        assert_eq!(Message::<H, 8>::from_bytes(&frame).as_ref(), Ok(&message));
+        assert_eq!(
+            Message::<H, 8>::read_from(&mut &frame[..]).as_ref(),
+            Ok(&message)
+        );
+        assert_eq!(crate::frame::frame_len(&frame), Some(frame.len()));
+
+        let short: crate::FrameBuffer = crate::DefaultMessage::ping().to_bytes().unwrap();
+        assert_eq!(short[2], PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn test_validate() {
+        use crate::{DefaultMessage, MessageBuilder, ParseError};
+
+        let raw = |header: &str| {
+            MessageBuilder::new()
+                .set_raw_header(heapless::String::try_from(header).unwrap())
+                .build()
+                .unwrap()
+        };
+
+        assert_eq!(DefaultMessage::ping().validate(), Ok(()));
+        assert_eq!(raw("A: 1\r\nB: 2").validate(), Ok(()));
+        assert_eq!(
+            raw("A: 1\r\nmalformed").validate(),
+            Err(ParseError::InvalidHeader)
+        );
+        assert_eq!(
+            raw("Bad key: 1\r\nA:: 2").validate(),
+            Err(ParseError::InvalidHeader)
+        );
+        assert_eq!(raw("A: 1\rB: 2").validate(), Err(ParseError::InvalidHeader));
+
+        // The builder refuses this many lines, so make the message directly
+        let too_many = DefaultMessage {
+            header: heapless::String::try_from(["a: 1"; 17].join("\r\n").as_str()).unwrap(),
+            ..DefaultMessage::ping()
+        };
+        assert_eq!(too_many.validate(), Err(ParseError::TooManyHeaders));
+    }
+
+    #[test]
+    fn test_debug_hex() {
+        use std::format;
+
+        use crate::{DefaultMessage, MessageBuilder};
+
+        assert_eq!(
+            format!("{}", DefaultMessage::ping().debug_hex()),
+            "01(SOH) 02(STX) 02 03 00 00 00(NUL) ff ff 04(EOT)"
+        );
+
+        let message = MessageBuilder::new()
+            .add_header_str("A", "\u{1}")
+            .unwrap()
+            .set_body_str("b")
+            .unwrap()
+            .build()
+            .unwrap();
+        let hex = format!("{}", message.debug_hex());
+        assert!(hex.starts_with("01(SOH) 02(STX) 02 00 05 00 41 3a 20 1b(ESC) 21 00(NUL) "));
+        assert!(hex.contains(" 03(ETX) 02(STX) 62 00(NUL) "));
+        assert!(hex.ends_with(" 04(EOT)"));
+    }
+
+    #[test]
+    fn test_trailers() {
+        use crate::{BuildError, DefaultMessage, FrameDecoder, Message, MessageBuilder};
+
+        let message = MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .set_body_str("hello")
+            .unwrap()
+            .add_trailer("Hash", "abc")
+            .unwrap()
+            .add_trailer("Status", "done")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(message.trailers(), "Hash: abc\r\nStatus: done");
+        assert_eq!(message.trailer_value("hash"), Some("abc"));
+        assert_eq!(message.trailer_value("Id"), None);
+        assert_eq!(message.header_value("Hash"), None);
+
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        assert_eq!(DefaultMessage::from_bytes(&bytes), Ok(message.clone()));
+        assert_eq!(
+            DefaultMessage::read_from(&mut &bytes[..]),
+            Ok(message.clone())
+        );
+        assert_eq!(Message::peek_body(&bytes), Ok(&b"hello"[..]));
+
+        let borrowed = Message::from_bytes_ref(&bytes).unwrap();
+        assert_eq!(borrowed.body(), b"hello");
+        assert_eq!(borrowed.trailers(), b"Hash: abc\r\nStatus: done");
+        assert_eq!(borrowed.to_owned(), Ok(message.clone()));
+
+        let mut decoder: FrameDecoder = FrameDecoder::new();
+        decoder.push(&bytes).unwrap();
+        assert_eq!(decoder.poll(), Some(message));
+
+        let no_body = MessageBuilder::new()
+            .add_trailer("Hash", "abc")
+            .unwrap()
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = no_body.to_bytes().unwrap();
+        assert_eq!(DefaultMessage::from_bytes(&bytes), Ok(no_body.clone()));
+        assert_eq!(DefaultMessage::read_from(&mut &bytes[..]), Ok(no_body));
+        assert_eq!(Message::peek_body(&bytes), Ok(&b""[..]));
+
+        let long = [b'a'; crate::MAX_TRAILER_LEN];
+        let long = core::str::from_utf8(&long).unwrap();
+        assert_eq!(
+            MessageBuilder::new().add_trailer("Hash", long).err(),
+            Some(BuildError::TrailerTooLarge)
+        );
+        assert_eq!(
+            MessageBuilder::new().add_trailer("Hash", "a\nb").err(),
+            Some(BuildError::InvalidHeaderValue)
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_endian() {
+        use crate::frame::HEADER_LEN_OFFSET;
+        use crate::{DefaultMessage, Endianness, MessageBuilder, ParseError};
+
+        let message = MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        assert_eq!(
+            DefaultMessage::from_bytes_endian(&bytes, Endianness::Le),
+            Ok(message.clone())
+        );
+
+        let mut swapped = bytes.clone();
+        swapped.swap(HEADER_LEN_OFFSET, HEADER_LEN_OFFSET + 1);
+        assert_eq!(
+            DefaultMessage::from_bytes_endian(&swapped, Endianness::Be),
+            Ok(message)
+        );
+        assert_eq!(
+            DefaultMessage::from_bytes(&swapped),
+            Err(ParseError::HeaderLengthOverflow)
+        );
+    }
+
+    #[test]
+    fn test_seq() {
+        use crate::{DefaultMessage, FrameDecoder, Message, MessageBuilder};
+
+        // Both bytes of the sequence number are marker values
+        let message = MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .set_body_str("hello")
+            .unwrap()
+            .seq(0x0403)
+            .build()
+            .unwrap();
+        assert_eq!(message.seq(), Some(0x0403));
+
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        assert_eq!(&bytes[3..6], &[0x80, 0x03, 0x04]);
+        assert_eq!(DefaultMessage::from_bytes(&bytes), Ok(message.clone()));
+        assert_eq!(
+            DefaultMessage::read_from(&mut &bytes[..]),
+            Ok(message.clone())
+        );
+        assert_eq!(Message::from_bytes_ref(&bytes).unwrap().seq(), Some(0x0403));
+        assert_eq!(Message::peek_header(&bytes), Ok(&b"Id: 1"[..]));
+
+        let mut decoder: FrameDecoder = FrameDecoder::new();
+        decoder.push(&bytes).unwrap();
+        assert_eq!(decoder.poll().unwrap().seq(), Some(0x0403));
+
+        let hex = std::format!("{}", message.debug_hex());
+        assert!(hex.starts_with("01(SOH) 02(STX) 02 80 03 04 05 00 "));
+
+        let plain = MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(plain.seq(), None);
+        assert!(!plain.eq_ignoring_header_order(&message));
+        let bytes: crate::FrameBuffer = plain.to_bytes().unwrap();
+        assert_eq!(DefaultMessage::from_bytes(&bytes).unwrap().seq(), None);
+    }
+
+    #[test]
+    fn test_min_frame_size_for_header() {
+        use crate::{DefaultMessage, Message, MessageBuilder};
+
+        assert_eq!(Message::min_frame_size_for_header(0, false), Some(10));
+        let ping: crate::FrameBuffer = DefaultMessage::ping().to_bytes().unwrap();
+        assert_eq!(
+            Some(ping.len()),
+            Message::min_frame_size_for_header(0, false)
+        );
+
+        let message = MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        assert_eq!(
+            Some(bytes.len()),
+            Message::min_frame_size_for_header(5, false)
+        );
+        assert_eq!(crate::frame::min_frame_len(&bytes), Some(bytes.len()));
+
+        let message = MessageBuilder::new()
+            .seq(1)
+            .add_header_str("Id", "1")
+            .unwrap()
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        assert_eq!(
+            Some(bytes.len()),
+            Message::min_frame_size_for_header(5, true)
+        );
+
+        // Headers too long for a 2 byte length get 4 bytes of it
+        assert_eq!(
+            Message::min_frame_size_for_header(0x10000, false),
+            Some(0x10000 + 12)
+        );
+        assert_eq!(Message::min_frame_size_for_header(usize::MAX, true), None);
+    }
+
+    #[test]
+    fn test_remove_header() {
+        use crate::MessageBuilder;
+
+        let mut builder = MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .add_header_str("Connection", "close")
+            .unwrap()
+            .add_header_str("Type", "a")
+            .unwrap();
+        assert!(builder.remove_header("connection"));
+        assert!(!builder.remove_header("Connection"));
+        assert_eq!(builder.header_count(), 2);
+
+        let message = builder
+            .add_header_str("Connection", "keep")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(message.header(), "Id: 1\r\nType: a\r\nConnection: keep");
+    }
+
+    #[test]
+    fn test_default() {
+        use crate::{DefaultMessage, Message};
+
+        let message = DefaultMessage::default();
+        assert!(message.is_ping());
+        assert_eq!(message.header(), "");
+        assert!(!message.has_body());
+
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        assert_eq!(&bytes[..], [1, 2, 2, 3, 0, 0, 0, 0xff, 0xff, 4]);
+        assert_eq!(DefaultMessage::from_bytes(&bytes), Ok(message));
+
+        let queue: heapless::Vec<Message<8, 8>, 4> =
+            core::iter::repeat_with(Message::default).take(4).collect();
+        assert!(queue.iter().all(Message::is_ping));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_body_str_lossy() {
+        use alloc::borrow::Cow;
+
+        let message = crate::MessageBuilder::new()
+            .set_body_bytes(heapless::Vec::from_slice(b"ok \xff done").unwrap())
+            .build()
+            .unwrap();
+        assert!(message.body_str().is_err());
+        assert_eq!(message.body_str_lossy(), "ok \u{fffd} done");
+
+        let message = crate::MessageBuilder::new()
+            .set_body_str("fine")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(matches!(message.body_str_lossy(), Cow::Borrowed("fine")));
+    }
+
+    #[test]
+    fn test_ord() {
+        use core::cmp::Ordering;
+
+        use crate::{FrameType, MessageBuilder};
+
+        let build = |key: &str, body: Option<&str>| {
+            let builder = MessageBuilder::new().add_header_str(key, "1").unwrap();
+            match body {
+                Some(body) => builder.set_body_str(body).unwrap(),
+                None => builder,
+            }
+            .build()
+            .unwrap()
+        };
+
+        assert!(build("A", Some("z")) < build("B", Some("a")));
+        assert!(build("A", Some("a")) < build("A", Some("b")));
+        assert!(build("A", None) < build("A", Some("")));
+        assert_eq!(
+            build("A", Some("a")).cmp(&build("A", Some("a"))),
+            Ordering::Equal
+        );
+
+        let event = MessageBuilder::new()
+            .add_header_str("A", "1")
+            .unwrap()
+            .frame_type(FrameType::Event)
+            .build()
+            .unwrap();
+        assert_ne!(build("A", None), event);
+        assert!(build("A", None) < event);
+    }
+
+    #[test]
+    fn test_builder_headers() {
+        use crate::MessageBuilder;
+
+        let builder = MessageBuilder::new()
+            .method("GET")
+            .unwrap()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .append_header(
+                String::from_str("Tag").unwrap(),
+                String::from_str("a").unwrap(),
+            )
+            .unwrap()
+            .append_header(
+                String::from_str("Tag").unwrap(),
+                String::from_str("b").unwrap(),
+            )
+            .unwrap();
+
+        assert!(builder
+            .headers()
+            .eq([("Id", "1"), ("Tag", "a"), ("Tag", "b")]));
+        assert_eq!(builder.get_header("tag"), Some("a"));
+        assert_eq!(builder.get_header("Method"), None);
+
+        let builder = if builder.get_header("Content-Type").is_none() {
+            builder
+                .add_header_str("Content-Type", "text/plain")
+                .unwrap()
+        } else {
+            builder
+        };
+        assert_eq!(builder.get_header("content-type"), Some("text/plain"));
+    }
+
+    #[test]
+    fn test_canonical_bytes() {
+        use crate::{DefaultMessage, MessageBuilder};
+
+        let a = MessageBuilder::new()
+            .add_header_str("Content-Type", "text/plain")
+            .unwrap()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+        let b = MessageBuilder::new()
+            .set_raw_header(String::from_str("id:1\nCONTENT-TYPE:   text/plain ").unwrap())
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_ne!(a, b);
+
+        let canonical: crate::FrameBuffer = a.canonical_bytes().unwrap();
+        assert_eq!(b.canonical_bytes(), Some(canonical.clone()));
+        assert_eq!(
+            DefaultMessage::from_bytes(&canonical).unwrap().header(),
+            "content-type: text/plain\r\nid: 1"
+        );
+
+        let other = MessageBuilder::new()
+            .add_header_str("Id", "2")
+            .unwrap()
+            .add_header_str("Content-Type", "text/plain")
+            .unwrap()
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_ne!(other.canonical_bytes(), Some(canonical));
+    }
+
+    #[test]
+    fn test_empty_header_parts() {
+        use crate::{BuildError, DefaultMessage, MessageBuilder};
+
+        assert_eq!(
+            MessageBuilder::new()
+                .try_add_header(String::new(), String::from_str("x").unwrap())
+                .err(),
+            Some(BuildError::EmptyHeaderKey)
+        );
+        assert!(MessageBuilder::new().add_header_str("", "x").is_err());
+        assert_eq!(
+            MessageBuilder::new().add_trailer("", "x").err(),
+            Some(BuildError::EmptyHeaderKey)
+        );
+        assert_eq!(
+            DefaultMessage::from_parts(String::from_str(": x").unwrap(), None),
+            Err(BuildError::EmptyHeaderKey)
+        );
+
+        let message = MessageBuilder::new()
+            .add_header_str("X", "")
+            .unwrap()
+            .add_header_str("Y", "1")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(message.header(), "X: \r\nY: 1");
+
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        let parsed = DefaultMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, message);
+        assert_eq!(parsed.header_value("X"), Some(""));
+        assert_eq!(parsed.header_value("Y"), Some("1"));
+        assert_eq!(parsed.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_read_from_timeout() {
+        use crate::io::UnexpectedEof;
+        use crate::{DefaultMessage, MessageBuilder, ReadError};
+
+        let message = MessageBuilder::new()
+            .add_header_str("Id", "1")
+            .unwrap()
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+
+        let mut stream = &bytes[..];
+        assert_eq!(
+            DefaultMessage::read_from_timeout(&mut stream, || false),
+            Ok(message)
+        );
+        assert!(stream.is_empty());
+
+        // The clock runs out partway through the frame
+        let mut reads = 0;
+        let expired = || {
+            reads += 1;
+            reads > 4
+        };
+        assert_eq!(
+            DefaultMessage::read_from_timeout(&mut &bytes[..], expired),
+            Err(ReadError::Timeout)
+        );
+
+        assert_eq!(
+            DefaultMessage::read_from_timeout(&mut &bytes[..8], || false),
+            Err(ReadError::Io(UnexpectedEof))
+        );
+    }
+
+    #[test]
+    fn test_header_entry() {
+        use crate::{BuildError, MessageBuilder};
+
+        let mut builder = MessageBuilder::new().add_header_str("Accept", "a").unwrap();
+
+        let accept = builder
+            .header_entry(String::from_str("accept").unwrap())
+            .unwrap();
+        assert_eq!(accept, "a");
+        accept.push_str(", b").unwrap();
+
+        let id = builder
+            .header_entry(String::from_str("Id").unwrap())
+            .unwrap();
+        assert_eq!(id, "");
+        id.push('1').unwrap();
+
+        assert_eq!(
+            builder.header_entry(String::new()),
+            Err(BuildError::EmptyHeaderKey)
+        );
+        assert_eq!(builder.header_count(), 2);
+
+        let message = builder.build().unwrap();
+        assert_eq!(message.header(), "Accept: a, b\r\nId: 1");
+
+        let mut builder = MessageBuilder::new();
+        builder
+            .header_entry(String::from_str("Id").unwrap())
+            .unwrap()
+            .push_str("1\r\nEvil: 1")
+            .unwrap();
+        assert_eq!(builder.build(), Err(BuildError::InvalidHeaderValue));
+    }
+
+    #[test]
+    fn test_response() {
+        use crate::{BuildError, DefaultMessage, FrameType};
+
+        let message = DefaultMessage::response(404, "text/plain", "not found").unwrap();
+        assert_eq!(message.frame_type(), FrameType::Response);
+        assert_eq!(message.status(), Some(404));
+        assert_eq!(message.header_value("content-type"), Some("text/plain"));
+        assert_eq!(message.body_str(), Ok("not found"));
+        assert_eq!(message.header(), "Status: 404\r\nContent-Type: text/plain");
+
+        let bytes = message.to_bytes::<256>().unwrap();
+        assert_eq!(DefaultMessage::from_bytes(&bytes), Ok(message));
+
+        assert_eq!(
+            DefaultMessage::response(200, "text/plain\r\nEvil: 1", ""),
+            Err(BuildError::InvalidHeaderValue)
+        );
+        assert_eq!(
+            DefaultMessage::response(200, "text/plain", &"a".repeat(crate::MAX_BODY_SIZE + 1)),
+            Err(BuildError::BodyTooLarge)
+        );
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log_decode() {
+        use std::{format, string::String as StdString, sync::Mutex, vec::Vec as StdVec};
+
+        use crate::{DefaultMessage, MessageBuilder};
+
+        static RECORDS: Mutex<StdVec<StdString>> = Mutex::new(StdVec::new());
+
+        struct Recorder;
+
+        impl log::Log for Recorder {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                let line = format!("{} {}", record.level(), record.args());
+                RECORDS.lock().unwrap().push(line);
+            }
+
+            fn flush(&self) {}
+        }
+
+        log::set_logger(&Recorder).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+
+        let message = MessageBuilder::new()
+            .add_header_str("Id", "7")
+            .unwrap()
+            .set_body_str("log")
+            .unwrap()
+            .build()
+            .unwrap();
+        let bytes = message.to_bytes::<64>().unwrap();
+        DefaultMessage::from_bytes(&bytes).unwrap();
+
+        let mut bad = bytes.clone();
+        *bad.last_mut().unwrap() = 0xaa;
+        DefaultMessage::from_bytes(&bad).unwrap_err();
+
+        // Other tests decode frames at the same time, so only look for ours
+        let records = RECORDS.lock().unwrap();
+        assert!(records
+            .iter()
+            .any(|r| r == "DEBUG decoded frame with a 5 byte header and a 3 byte body"));
+        assert!(records.iter().any(|r| r
+            .starts_with("WARN failed to decode frame starting [01, 02,")
+            && r.ends_with("]: BadEndMarker { found: 170 }")));
+    }
+
+    #[test]
+    fn test_first_line_counts_as_header() {
+        use crate::{BuildError, DefaultMessage, MessageBuilder, MAX_HEADERS};
+
+        let full = || {
+            let mut builder = MessageBuilder::new().status(200);
+            for i in 0..MAX_HEADERS - 1 {
+                let key: String<32> = String::try_from(i as u32).unwrap();
+                builder = builder.try_add_header(key, String::new()).unwrap();
+            }
+            builder
+        };
+        assert_eq!(
+            full()
+                .try_add_header(String::from_str("Last").unwrap(), String::new())
+                .err(),
+            Some(BuildError::TooManyHeaders)
+        );
+
+        let message = full().build().unwrap();
+        assert_eq!(message.header_count(), MAX_HEADERS);
+        let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+        assert_eq!(DefaultMessage::from_bytes(&bytes), Ok(message));
+
+        // Headers added around the check still can't go over at build time
+        let over = full()
+            .add_header(String::from_str("Last").unwrap(), String::new())
+            .unwrap();
+        assert_eq!(over.build(), Err(BuildError::TooManyHeaders));
+
+        let raw = MessageBuilder::new()
+            .status(200)
+            .set_raw_header(String::from_str("A: 1\r\nB: 2").unwrap());
+        assert_eq!(raw.line_count(), 3);
+    }
+
+    #[test]
+    fn test_short_input() {
+        use crate::{DefaultMessage, ParseError};
+
+        assert_eq!(DefaultMessage::from_bytes(&[]), Err(ParseError::TooShort));
+        assert_eq!(
+            DefaultMessage::from_bytes(&[1, 2]),
+            Err(ParseError::TooShort)
+        );
+        assert_eq!(
+            DefaultMessage::from_bytes(&[1, 2, 2, 0, 0xff, 0, 4]),
+            Err(ParseError::HeaderLengthOverflow)
+        );
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_round_trip(
+            headers in proptest::collection::vec(
+                ("[A-Za-z][A-Za-z0-9-]{0,31}", "[ -~]{0,128}"),
+                0..=16,
+            ),
+            body in proptest::option::of(
+                proptest::collection::vec(proptest::num::u8::ANY, 0..=crate::MAX_BODY_SIZE),
+            ),
+        ) {
+            use crate::{DefaultMessage, MessageBuilder};
+
+            let mut unique = std::vec::Vec::new();
+            for (k, v) in &headers {
+                if !unique.iter().any(|(other, _): &(&str, &str)| other.eq_ignore_ascii_case(k)) {
+                    unique.push((k.as_str(), v.as_str()));
+                }
+            }
+
+            let mut builder = MessageBuilder::new();
+            for (k, v) in &unique {
+                builder = builder.add_header_str(k, v).unwrap();
+            }
+            if let Some(body) = &body {
+                builder = builder.set_body_bytes(heapless::Vec::from_slice(body).unwrap());
+            }
+
+            let Ok(message) = builder.build() else {
+                // The headers don't fit in the header block
+                return Ok(());
+            };
+
+            let bytes: crate::FrameBuffer = message.to_bytes().unwrap();
+            let parsed = DefaultMessage::from_bytes(&bytes).unwrap();
+
+            proptest::prop_assert_eq!(&parsed, &message);
+            proptest::prop_assert!(parsed
+                .header_iter()
+                .eq(unique.iter().copied()));
+            proptest::prop_assert_eq!(parsed.has_body(), body.is_some());
+            proptest::prop_assert_eq!(parsed.body(), body.as_deref().unwrap_or_default());
+            proptest::prop_assert_eq!(DefaultMessage::read_from(&mut &bytes[..]), Ok(message));
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! `serde` support for [`Message`], enabled by the `serde` feature.
+//!
+//! A message is represented as a struct with a `header` string field, an
+//! optional `body` string field, which is `None` for a message without a
+//! body, and the `frame_type`, `trailers` and optional `seq` of the frame.
+//! The last three can be left out when deserializing, for a request with no
+//! trailers or sequence number. Only bodies that are valid UTF-8 can be
+//! serialized, and a header or trailer block that is not made of
+//! `key: value` lines is refused when deserializing.
+
+use heapless::String;
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::message::check_header_block;
+use crate::{FrameType, Message, MAX_TRAILER_LEN};
+
+#[derive(Serialize)]
+#[serde(rename = "Message")]
+struct MessageRepr<'a> {
+    header: &'a str,
+    body: Option<&'a str>,
+    frame_type: FrameType,
+    trailers: &'a str,
+    seq: Option<u16>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Message")]
+struct OwnedMessageRepr<const H: usize, const B: usize> {
+    header: String<H>,
+    body: Option<String<B>>,
+    #[serde(default)]
+    frame_type: FrameType,
+    #[serde(default)]
+    trailers: String<MAX_TRAILER_LEN>,
+    #[serde(default)]
+    seq: Option<u16>,
+}
+
+impl<const H: usize, const B: usize> Serialize for Message<H, B> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MessageRepr {
+            header: &self.header,
+            body: self
+                .has_body()
+                .then(|| self.body_str())
+                .transpose()
+                .map_err(|_| ser::Error::custom("body is not valid UTF-8"))?,
+            frame_type: self.frame_type,
+            trailers: &self.trailers,
+            seq: self.seq,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, const H: usize, const B: usize> Deserialize<'de> for Message<H, B> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = OwnedMessageRepr::<H, B>::deserialize(deserializer)?;
+        check_header_block(&repr.header).map_err(de::Error::custom)?;
+        check_header_block(&repr.trailers).map_err(de::Error::custom)?;
+
+        Ok(Message {
+            frame_type: repr.frame_type,
+            header: repr.header,
+            body: repr.body.map(String::into_bytes),
+            trailers: repr.trailers,
+            seq: repr.seq,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_test::{assert_de_tokens, assert_de_tokens_error, assert_tokens, Token};
+
+    use crate::{DefaultMessage, FrameType, MessageBuilder};
+
+    #[test]
+    fn test_tokens() {
+        let message = MessageBuilder::new()
+            .add_header_str("Content-Type", "text/plain")
+            .unwrap()
+            .set_body_str("hello")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_tokens(
+            &message,
+            &[
+                Token::Struct {
+                    name: "Message",
+                    len: 5,
+                },
+                Token::Str("header"),
+                Token::Str("Content-Type: text/plain"),
+                Token::Str("body"),
+                Token::Some,
+                Token::Str("hello"),
+                Token::Str("frame_type"),
+                Token::UnitVariant {
+                    name: "FrameType",
+                    variant: "Request",
+                },
+                Token::Str("trailers"),
+                Token::Str(""),
+                Token::Str("seq"),
+                Token::None,
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_frame_fields() {
+        let message = MessageBuilder::new()
+            .frame_type(FrameType::Response)
+            .seq(7)
+            .set_body_str("done")
+            .unwrap()
+            .add_trailer("Checksum", "abc")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_tokens(
+            &message,
+            &[
+                Token::Struct {
+                    name: "Message",
+                    len: 5,
+                },
+                Token::Str("header"),
+                Token::Str(""),
+                Token::Str("body"),
+                Token::Some,
+                Token::Str("done"),
+                Token::Str("frame_type"),
+                Token::UnitVariant {
+                    name: "FrameType",
+                    variant: "Response",
+                },
+                Token::Str("trailers"),
+                Token::Str("Checksum: abc"),
+                Token::Str("seq"),
+                Token::Some,
+                Token::U16(7),
+                Token::StructEnd,
+            ],
+        );
+
+        // The frame fields default to a request without trailers or a
+        // sequence number
+        assert_de_tokens(
+            &MessageBuilder::new().build().unwrap(),
+            &[
+                Token::Struct {
+                    name: "Message",
+                    len: 2,
+                },
+                Token::Str("header"),
+                Token::Str(""),
+                Token::Str("body"),
+                Token::None,
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        for (header, error) in [
+            ("no separator", "header name or value is malformed"),
+            ("Id: 1\r\nno separator", "header name or value is malformed"),
+            ("Id: 1\rAdmin: yes", "header value has a line break"),
+        ] {
+            assert_de_tokens_error::<DefaultMessage>(
+                &[
+                    Token::Struct {
+                        name: "Message",
+                        len: 2,
+                    },
+                    Token::Str("header"),
+                    Token::Str(header),
+                    Token::Str("body"),
+                    Token::None,
+                    Token::StructEnd,
+                ],
+                error,
+            );
+        }
+    }
+}